@@ -0,0 +1,59 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields};
+
+// #[derive(Decoder)] for structs with named fields. Fields are decoded and
+// encoded in declaration order with rd.read() / wd.write(), so every field
+// type has to implement Decoder - primitives, FString, Tag, [u8; N] arrays,
+// u32 count prefixed Vec<T> and other derived structs.
+//
+// Generated code refers to Decoder, ReadStream, WriteStream and FError by
+// their full crate::fot paths, so deriving module doesn't need to import them
+#[proc_macro_derive(Decoder)]
+pub fn derive_decoder(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Error::new_spanned(name, "Decoder derive requires named fields")
+                    .to_compile_error()
+                    .into()
+            }
+        },
+        _ => {
+            return Error::new_spanned(name, "Decoder derive supports only structs")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    let names: Vec<_> = fields.iter().map(|f| &f.ident).collect();
+    let expanded = quote! {
+        impl #impl_generics crate::fot::decoder::Decoder for #name #ty_generics #where_clause {
+            fn decode<'a>(
+                rd: &mut crate::fot::stream::ReadStream<'a>,
+            ) -> ::std::result::Result<Self, crate::fot::ferror::FError> {
+                ::std::result::Result::Ok(#name {
+                    #(#names: rd.read()?,)*
+                })
+            }
+
+            fn encode(
+                &self,
+                wd: &mut crate::fot::stream::WriteStream,
+            ) -> ::std::result::Result<(), crate::fot::ferror::FError> {
+                #(wd.write(&self.#names)?;)*
+                ::std::result::Result::Ok(())
+            }
+
+            fn get_enc_size(&self) -> usize {
+                0 #(+ crate::fot::decoder::Decoder::get_enc_size(&self.#names))*
+            }
+        }
+    };
+    expanded.into()
+}
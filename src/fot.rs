@@ -1,14 +1,21 @@
 pub mod attributes;
 pub mod decoder;
+pub mod dialogs;
 pub mod entity;
 pub mod entitylist;
 pub mod esh;
+pub mod inventory;
 pub mod ferror;
+pub mod flags;
 pub mod fstring;
+pub mod level;
 pub mod raw;
+pub mod refgraph;
 pub mod save;
 pub mod sgd;
+pub mod sheet;
 pub mod ssg;
 pub mod stream;
 pub mod tag;
+pub mod template;
 pub mod world;
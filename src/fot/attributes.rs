@@ -255,6 +255,17 @@ const ADDICTIONS: [&str; 10] = [
     "drunk",
 ];
 
+pub const GROUPS: [&str; 8] = [
+    "stats",
+    "traits",
+    "derived",
+    "skills",
+    "skill_tags",
+    "opt_traits",
+    "perks",
+    "addictions",
+];
+
 #[derive(Debug)]
 pub struct Attributes {
     esh: ESH,
@@ -292,34 +303,34 @@ impl Attributes {
         let mut addictions: IndexMap<&'static str, u32> = IndexMap::with_capacity(10);
 
         if let ESHValue::Binary(binary) = &esh.props["esbin"] {
-            let mut rd = ReadStream::new(&binary, 0);
+            let mut rd = ReadStream::new(binary, 0);
 
             let size2 = rd.read_u32()?;
             let tag: Tag = rd.read()?;
 
-            for i in 0..MAX_STATS {
-                stats.insert(STATS[i], rd.read_u32()?);
+            for name in STATS.iter().take(MAX_STATS) {
+                stats.insert(name, rd.read_u32()?);
             }
-            for i in 0..MAX_TRAITS {
-                traits.insert(TRAITS[i], rd.read_u32()?);
+            for name in TRAITS.iter().take(MAX_TRAITS) {
+                traits.insert(name, rd.read_u32()?);
             }
-            for i in 0..MAX_DERIVED {
-                derived.insert(DERIVED[i], rd.read_u32()?);
+            for name in DERIVED.iter().take(MAX_DERIVED) {
+                derived.insert(name, rd.read_u32()?);
             }
-            for i in 0..MAX_SKILLS {
-                skills.insert(SKILLS[i], rd.read_u32()?);
+            for name in SKILLS.iter().take(MAX_SKILLS) {
+                skills.insert(name, rd.read_u32()?);
             }
-            for i in 0..MAX_SKILLS {
-                skill_tags.insert(SKILLS[i], rd.read_bool()?);
+            for name in SKILLS.iter().take(MAX_SKILLS) {
+                skill_tags.insert(name, rd.read_bool()?);
             }
-            for i in 0..MAX_OPT_TRAITS {
-                opt_traits.insert(OPT_TRAITS[i], rd.read_bool()?);
+            for name in OPT_TRAITS.iter().take(MAX_OPT_TRAITS) {
+                opt_traits.insert(name, rd.read_bool()?);
             }
-            for i in 0..MAX_PERKS {
-                perks.insert(PERKS[i], rd.read_u32()?);
+            for name in PERKS.iter().take(MAX_PERKS) {
+                perks.insert(name, rd.read_u32()?);
             }
-            for i in 0..MAX_ADDICTIONS {
-                addictions.insert(ADDICTIONS[i], rd.read_u32()?);
+            for name in ADDICTIONS.iter().take(MAX_ADDICTIONS) {
+                addictions.insert(name, rd.read_u32()?);
             }
 
             let enc_size = binary.len();
@@ -339,7 +350,7 @@ impl Attributes {
                 addictions,
             })
         } else {
-            return Err(FE::ValueNoESBIN);
+            Err(FE::ValueNoESBIN)
         }
     }
 
@@ -385,4 +396,118 @@ impl Attributes {
 
         Ok(wd.into_vec())
     }
+
+    pub fn get_group(&self, group: &str) -> Result<Vec<(&'static str, String)>, FE> {
+        fn values<V: ToString>(map: &IndexMap<&'static str, V>) -> Vec<(&'static str, String)> {
+            map.iter().map(|(&k, v)| (k, v.to_string())).collect()
+        }
+
+        Ok(match group {
+            "stats" => values(&self.stats),
+            "traits" => values(&self.traits),
+            "derived" => values(&self.derived),
+            "skills" => values(&self.skills),
+            "skill_tags" => values(&self.skill_tags),
+            "opt_traits" => values(&self.opt_traits),
+            "perks" => values(&self.perks),
+            "addictions" => values(&self.addictions),
+            _ => return Err(FE::NoAttributeGroup(group.to_string())),
+        })
+    }
+
+    pub fn set_value(&mut self, group: &str, name: &str, value: &str) -> Result<(), FE> {
+        fn set<V: std::str::FromStr>(
+            map: &mut IndexMap<&'static str, V>,
+            name: &str,
+            value: &str,
+        ) -> Result<(), FE> {
+            let val = match map.get_mut(name) {
+                Some(val) => val,
+                None => return Err(FE::NoAttribute(name.to_string())),
+            };
+            *val = value
+                .parse()
+                .map_err(|_| FE::InvalidValue(value.to_string()))?;
+            Ok(())
+        }
+
+        match group {
+            "stats" => set(&mut self.stats, name, value),
+            "traits" => set(&mut self.traits, name, value),
+            "derived" => set(&mut self.derived, name, value),
+            "skills" => set(&mut self.skills, name, value),
+            "skill_tags" => set(&mut self.skill_tags, name, value),
+            "opt_traits" => set(&mut self.opt_traits, name, value),
+            "perks" => set(&mut self.perks, name, value),
+            "addictions" => set(&mut self.addictions, name, value),
+            _ => Err(FE::NoAttributeGroup(group.to_string())),
+        }
+    }
+
+    // modifiers are stored as u32 but may hold negative values
+    pub fn get_effective_stats(&self, mods: Option<&Attributes>) -> IndexMap<&'static str, i32> {
+        self.stats
+            .iter()
+            .map(|(&name, &value)| {
+                let m = mods.map_or(0, |mods| mods.stats[name] as i32);
+                (name, value as i32 + m)
+            })
+            .collect()
+    }
+
+    // skill value as game shows it - initial value from SPECIAL,
+    // plus attribute and modifier points, plus 20 when tagged
+    pub fn get_effective_skills(&self, mods: Option<&Attributes>) -> IndexMap<&'static str, i32> {
+        let st = self.get_effective_stats(mods);
+        let (s, p, e, c, i, a, l) = (
+            st["strength"],
+            st["perception"],
+            st["endurance"],
+            st["charisma"],
+            st["intelligence"],
+            st["agility"],
+            st["luck"],
+        );
+
+        self.skills
+            .iter()
+            .map(|(&name, &value)| {
+                let base = match name {
+                    "smallGuns" => 5 + 4 * a,
+                    "bigGuns" => 2 * a,
+                    "energyWeapons" => 2 * a,
+                    "unarmed" => 30 + 2 * (a + s),
+                    "meleeWeapons" => 20 + 2 * (a + s),
+                    "throwing" => 4 * a,
+                    "firstAid" => 2 * (p + i),
+                    "doctor" => 5 + p + i,
+                    "sneak" => 5 + 3 * a,
+                    "lockpick" => 10 + p + a,
+                    "steal" => 3 * a,
+                    "traps" => 10 + p + a,
+                    "science" => 4 * i,
+                    "repair" => 3 * i,
+                    "pilot" => 2 * (a + p),
+                    "barter" => 4 * c,
+                    "gambling" => 5 * l,
+                    "outdoorsman" => 2 * (e + i),
+                    _ => 0,
+                };
+                let m = mods.map_or(0, |mods| mods.skills[name] as i32);
+                let tag = if self.skill_tags[name] { 20 } else { 0 };
+                (name, base + value as i32 + m + tag)
+            })
+            .collect()
+    }
+
+    pub fn get_lev_since_perk(&self) -> Result<i32, FE> {
+        match self.esh.get("levSincePerk") {
+            Some(ESHValue::Int(val)) => Ok(*val),
+            _ => Err(FE::NoESHValue),
+        }
+    }
+
+    pub fn set_lev_since_perk(&mut self, val: i32) {
+        self.esh.set("levSincePerk", ESHValue::Int(val));
+    }
 }
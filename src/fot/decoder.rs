@@ -36,3 +36,116 @@ impl Decoder for String {
         self.len() + 1
     }
 }
+
+// primitives for #[derive(Decoder)] structs
+macro_rules! impl_primitive {
+    ($ty:ty, $read:ident, $write:ident) => {
+        impl Decoder for $ty {
+            fn decode<'a>(rd: &mut ReadStream<'a>) -> Result<Self, FE> {
+                rd.$read()
+            }
+
+            fn encode(&self, wd: &mut WriteStream) -> Result<(), FE> {
+                wd.$write(*self)
+            }
+
+            fn get_enc_size(&self) -> usize {
+                std::mem::size_of::<$ty>()
+            }
+        }
+    };
+}
+
+impl_primitive!(u8, read_u8, write_u8);
+impl_primitive!(bool, read_bool, write_bool);
+impl_primitive!(u16, read_u16, write_u16);
+impl_primitive!(i32, read_i32, write_i32);
+impl_primitive!(u32, read_u32, write_u32);
+impl_primitive!(f32, read_f32, write_f32);
+
+impl<const N: usize> Decoder for [u8; N] {
+    fn decode<'a>(rd: &mut ReadStream<'a>) -> Result<Self, FE> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(rd.as_bytes(N)?);
+        Ok(bytes)
+    }
+
+    fn encode(&self, wd: &mut WriteStream) -> Result<(), FE> {
+        wd.write_bytes(self);
+        Ok(())
+    }
+
+    fn get_enc_size(&self) -> usize {
+        N
+    }
+}
+
+// u32 count followed by elements
+impl<T: Decoder> Decoder for Vec<T> {
+    fn decode<'a>(rd: &mut ReadStream<'a>) -> Result<Self, FE> {
+        let n = rd.read_u32()? as usize;
+        let mut vec: Vec<T> = Vec::with_capacity(n);
+        for _ in 0..n {
+            vec.push(rd.read()?);
+        }
+        Ok(vec)
+    }
+
+    fn encode(&self, wd: &mut WriteStream) -> Result<(), FE> {
+        wd.write_u32(self.len() as u32)?;
+        for val in self.iter() {
+            wd.write(val)?;
+        }
+        Ok(())
+    }
+
+    fn get_enc_size(&self) -> usize {
+        4 + self.iter().map(|val| val.get_enc_size()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // decode whole bytes as T, then check that encoding gives same bytes
+    pub(crate) fn round_trip<T: Decoder>(bytes: &[u8]) -> T {
+        let mut rd = ReadStream::new(bytes, 0);
+        let val: T = rd.read().unwrap();
+        assert_eq!(rd.offset(), bytes.len());
+        assert_eq!(val.get_enc_size(), bytes.len());
+
+        let mut wd = WriteStream::new(bytes.len());
+        wd.write(&val).unwrap();
+        assert_eq!(wd.into_vec(), bytes);
+        val
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(round_trip::<u8>(&[0xab]), 0xab);
+        assert!(round_trip::<bool>(&[1]));
+        assert!(!round_trip::<bool>(&[0]));
+        assert_eq!(round_trip::<u16>(&[0x34, 0x12]), 0x1234);
+        assert_eq!(round_trip::<i32>(&[0xfe, 0xff, 0xff, 0xff]), -2);
+        assert_eq!(round_trip::<u32>(&[0x78, 0x56, 0x34, 0x12]), 0x12345678);
+        assert_eq!(round_trip::<f32>(&1.5f32.to_le_bytes()), 1.5);
+        assert_eq!(round_trip::<String>(b"<sgd>\0"), "<sgd>");
+    }
+
+    #[test]
+    fn byte_array() {
+        assert_eq!(round_trip::<[u8; 3]>(&[1, 2, 3]), [1, 2, 3]);
+        assert_eq!(round_trip::<[u8; 0]>(&[]), []);
+    }
+
+    #[test]
+    fn vec() {
+        let bytes = [2, 0, 0, 0, 1, 0, 2, 0];
+        assert_eq!(round_trip::<Vec<u16>>(&bytes), vec![1, 2]);
+        assert!(round_trip::<Vec<u32>>(&[0, 0, 0, 0]).is_empty());
+
+        let nested = [1, 0, 0, 0, 2, 0, 0, 0, 7, 8];
+        assert_eq!(round_trip::<Vec<Vec<u8>>>(&nested), vec![vec![7, 8]]);
+    }
+}
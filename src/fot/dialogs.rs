@@ -0,0 +1,363 @@
+use super::ferror::FError as FE;
+use super::sgd::SGD;
+use std::fs;
+use std::path::Path;
+
+// Dialog lines of SGD for translation, keyed by speaker and line index.
+// Exported as gettext PO (speaker:index in msgctxt, original line in msgid)
+// or as CSV with speaker,index,text,translation columns. On import only
+// lines with non-empty translation are written back into SGD
+
+const CSV_HEADER: &str = "speaker,index,text,translation";
+
+pub struct DialogLine {
+    pub speaker: String,
+    pub index: usize,
+    pub text: String,
+    pub translation: String,
+}
+
+#[derive(Default)]
+pub struct Dialogs {
+    pub lines: Vec<DialogLine>,
+}
+
+impl Dialogs {
+    pub fn from_sgd(sgd: &SGD) -> Self {
+        let mut lines: Vec<DialogLine> = Vec::new();
+        for (speaker, texts) in sgd.dialogs.iter() {
+            for (index, text) in texts.iter().enumerate() {
+                lines.push(DialogLine {
+                    speaker: speaker.to_string(),
+                    index,
+                    text: text.to_string(),
+                    translation: String::new(),
+                });
+            }
+        }
+        Dialogs { lines }
+    }
+
+    // returns number of replaced lines
+    pub fn apply(&self, sgd: &mut SGD) -> Result<usize, FE> {
+        let mut count = 0;
+        for line in self.lines.iter() {
+            if line.translation.is_empty() {
+                continue;
+            }
+
+            let text = sgd
+                .dialogs
+                .get_mut(line.speaker.as_str())
+                .and_then(|texts| texts.get_mut(line.index))
+                .ok_or_else(|| FE::NoDialogLine(line.speaker.clone(), line.index))?;
+
+            // check that translation fits code page before replacing line
+            let mut translated = text.clone();
+            translated.set_str(line.translation.clone());
+            translated.as_bytes()?;
+
+            *text = translated;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn to_po(&self) -> String {
+        let mut text =
+            String::from("msgid \"\"\nmsgstr \"Content-Type: text/plain; charset=UTF-8\\n\"\n");
+        for line in self.lines.iter() {
+            text.push_str(&format!(
+                "\nmsgctxt \"{}:{}\"\nmsgid \"{}\"\nmsgstr \"{}\"\n",
+                po_escape(&line.speaker),
+                line.index,
+                po_escape(&line.text),
+                po_escape(&line.translation)
+            ));
+        }
+        text
+    }
+
+    pub fn parse_po(text: &str) -> Result<Self, FE> {
+        let mut dialogs = Dialogs::default();
+        let mut entry: [Option<String>; 3] = [None, None, None];
+        let mut field: Option<usize> = None;
+        let mut entry_line = 0;
+        for (i, line) in text.lines().chain(std::iter::once("")).enumerate() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+
+            if line.is_empty() {
+                if let [Some(ctxt), Some(_), Some(msgstr)] = &entry {
+                    dialogs
+                        .lines
+                        .push(parse_po_entry(ctxt, msgstr, entry_line)?);
+                }
+                entry = [None, None, None];
+                field = None;
+                continue;
+            }
+
+            let (idx, rest) = if let Some(rest) = line.strip_prefix("msgctxt ") {
+                entry_line = i + 1;
+                (0, rest)
+            } else if let Some(rest) = line.strip_prefix("msgid ") {
+                (1, rest)
+            } else if let Some(rest) = line.strip_prefix("msgstr ") {
+                (2, rest)
+            } else {
+                // continuation of previous string
+                (field.ok_or(FE::DialogSyntax(i + 1))?, line)
+            };
+
+            let value = po_unescape(rest).ok_or(FE::DialogSyntax(i + 1))?;
+            entry[idx].get_or_insert_with(String::new).push_str(&value);
+            field = Some(idx);
+        }
+
+        Ok(dialogs)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut text = format!("{}\n", CSV_HEADER);
+        for line in self.lines.iter() {
+            text.push_str(&format!(
+                "{},{},{},{}\n",
+                csv_escape(&line.speaker),
+                line.index,
+                csv_escape(&line.text),
+                csv_escape(&line.translation)
+            ));
+        }
+        text
+    }
+
+    pub fn parse_csv(text: &str) -> Result<Self, FE> {
+        let mut dialogs = Dialogs::default();
+        for (i, (line, record)) in parse_csv_records(text)?.into_iter().enumerate() {
+            if i == 0 && record.join(",") == CSV_HEADER {
+                continue;
+            }
+
+            let [speaker, index, text, translation]: [String; 4] =
+                record.try_into().map_err(|_| FE::DialogSyntax(line))?;
+            dialogs.lines.push(DialogLine {
+                speaker,
+                index: index.parse().map_err(|_| FE::DialogSyntax(line))?,
+                text,
+                translation,
+            });
+        }
+        Ok(dialogs)
+    }
+
+    pub fn load_po(path: &Path) -> Result<Self, FE> {
+        Self::parse_po(&fs::read_to_string(path)?)
+    }
+
+    pub fn load_csv(path: &Path) -> Result<Self, FE> {
+        Self::parse_csv(&fs::read_to_string(path)?)
+    }
+}
+
+fn parse_po_entry(ctxt: &str, msgstr: &str, line: usize) -> Result<DialogLine, FE> {
+    let (speaker, index) = ctxt.rsplit_once(':').ok_or(FE::DialogSyntax(line))?;
+    Ok(DialogLine {
+        speaker: speaker.to_string(),
+        index: index.parse().map_err(|_| FE::DialogSyntax(line))?,
+        text: String::new(),
+        translation: msgstr.to_string(),
+    })
+}
+
+fn po_escape(str: &str) -> String {
+    str.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+}
+
+// quoted PO string into its value
+fn po_unescape(quoted: &str) -> Option<String> {
+    let inner = quoted.strip_prefix('"')?.strip_suffix('"')?;
+    let mut str = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            str.push(c);
+            continue;
+        }
+
+        match chars.next()? {
+            'n' => str.push('\n'),
+            't' => str.push('\t'),
+            c => str.push(c),
+        }
+    }
+    Some(str)
+}
+
+fn csv_escape(str: &str) -> String {
+    if str.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", str.replace('"', "\"\""))
+    } else {
+        str.to_string()
+    }
+}
+
+// RFC 4180 records with line they start at, quoted fields may contain
+// commas, quotes and newlines
+fn parse_csv_records(text: &str) -> Result<Vec<(usize, Vec<String>)>, FE> {
+    let mut records: Vec<(usize, Vec<String>)> = Vec::new();
+    let mut record: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut quote_line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => {
+                quoted = true;
+                quote_line = line;
+            }
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' => (),
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push((record_line, std::mem::take(&mut record)));
+                line += 1;
+                record_line = line;
+            }
+            c => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(FE::DialogSyntax(quote_line));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((record_line, record));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(speaker: &str, index: usize, text: &str, translation: &str) -> DialogLine {
+        DialogLine {
+            speaker: speaker.to_string(),
+            index,
+            text: text.to_string(),
+            translation: translation.to_string(),
+        }
+    }
+
+    fn dialogs() -> Dialogs {
+        Dialogs {
+            lines: vec![
+                line("Jo", 0, "Hello", "Привет"),
+                line("Jo", 1, "Say \"hi\", then\nleave", "a\\b\tc"),
+                line("Vault:13", 2, "", ""),
+            ],
+        }
+    }
+
+    fn fields(dialogs: &Dialogs) -> Vec<(&str, usize, &str)> {
+        dialogs
+            .lines
+            .iter()
+            .map(|l| (l.speaker.as_str(), l.index, l.translation.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn po_round_trip() {
+        let parsed = Dialogs::parse_po(&dialogs().to_po()).unwrap();
+        assert_eq!(fields(&parsed), fields(&dialogs()));
+    }
+
+    #[test]
+    fn po_continuations_and_comments() {
+        let text = "# comment\n\
+                    msgctxt \"Jo:\"\n\"7\"\n\
+                    msgid \"Hello\"\n\
+                    msgstr \"\"\n\"first \"\n\"second\\n\"\n\
+                    \n\
+                    #, fuzzy\n\
+                    msgctxt \"Jo:8\"\nmsgid \"x\"\nmsgstr \"\\\"y\\\"\"";
+        let parsed = Dialogs::parse_po(text).unwrap();
+        assert_eq!(
+            fields(&parsed),
+            vec![("Jo", 7, "first second\n"), ("Jo", 8, "\"y\"")]
+        );
+    }
+
+    #[test]
+    fn po_syntax_errors() {
+        let err = |text: &str| match Dialogs::parse_po(text) {
+            Err(FE::DialogSyntax(line)) => line,
+            _ => panic!("no syntax error in {:?}", text),
+        };
+        assert_eq!(err("\"continuation\""), 1);
+        assert_eq!(
+            err("msgctxt \"Jo:1\"\nmsgid \"unterminated\nmsgstr \"\""),
+            2
+        );
+        assert_eq!(err("\nmsgctxt \"Jo\"\nmsgid \"\"\nmsgstr \"x\""), 2);
+        assert_eq!(err("msgctxt \"Jo:x\"\nmsgid \"\"\nmsgstr \"x\""), 1);
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let parsed = Dialogs::parse_csv(&dialogs().to_csv()).unwrap();
+        assert_eq!(fields(&parsed), fields(&dialogs()));
+        assert_eq!(parsed.lines[1].text, "Say \"hi\", then\nleave");
+    }
+
+    #[test]
+    fn csv_records() {
+        let text = "Jo,0,Hello,Hi\r\n\"Jo, Jr\",1,\"a\"\"b\",\"x\r\ny\"";
+        let parsed = Dialogs::parse_csv(text).unwrap();
+        assert_eq!(
+            fields(&parsed),
+            vec![("Jo", 0, "Hi"), ("Jo, Jr", 1, "x\r\ny")]
+        );
+        assert_eq!(parsed.lines[1].text, "a\"b");
+    }
+
+    #[test]
+    fn csv_syntax_errors() {
+        let err = |text: &str| match Dialogs::parse_csv(text) {
+            Err(FE::DialogSyntax(line)) => line,
+            _ => panic!("no syntax error in {:?}", text),
+        };
+        assert_eq!(err("Jo,0,Hello\n"), 1);
+        assert_eq!(err("Jo,0,a,b\nJo,x,a,b\n"), 2);
+        assert_eq!(err("Jo,0,a,b\nJo,1,\"a\nb,c\n"), 2);
+        assert_eq!(err("Jo,0,\"a\nb\",c\nJo,x,a,b\n"), 3);
+    }
+}
@@ -3,31 +3,163 @@ use super::decoder::DecoderCtx;
 use super::entitylist::{EntityEncoding, EntityList};
 use super::esh::{ESHValue, ESH};
 use super::ferror::FError as FE;
+use super::flags::EntityFlags;
+use super::fstring::{CodePage, FString};
 use super::stream::{ReadStream, WriteStream};
+use std::cell::OnceCell;
+use std::rc::Rc;
 
 pub const NO_FLAGS: u32 = 0;
 pub const NO_ESH: usize = 0xFFFF;
 
+// encoded ESH bytes in shared world data
+#[derive(Clone)]
+struct RawESH {
+    data: Rc<[u8]>,
+    offset: usize,
+    size: usize,
+    codepage: CodePage,
+}
+
+impl RawESH {
+    fn as_bytes(&self) -> &[u8] {
+        &self.data[self.offset..self.offset + self.size]
+    }
+
+    fn decode(&self) -> Result<ESH, FE> {
+        // stream over world data, so errors report offsets in world
+        let mut rd = ReadStream::new(&self.data[..self.offset + self.size], self.offset)
+            .with_codepage(self.codepage);
+        rd.read().map_err(|fe| fe.context("ESH", rd.offset()))
+    }
+}
+
+// Lazy ESH is decoded on first access and written back from raw bytes
+// until it's borrowed mutably, so only modified entities are re-encoded
+#[derive(Clone)]
+enum EntityESH {
+    None,
+    Lazy(RawESH, OnceCell<ESH>),
+    Decoded(ESH),
+}
+
+#[derive(Clone)]
 pub struct Entity {
     pub flags: u32,
     pub type_idx: usize,
-    pub esh: Option<ESH>,
+    esh: EntityESH,
     enc_size: usize,
 }
 
 impl Entity {
-    pub fn get_esh(&self) -> Result<&ESH, FE> {
+    pub fn get_flags(&self) -> EntityFlags {
+        EntityFlags::from_bits_retain(self.flags)
+    }
+
+    pub fn set_flags(&mut self, flags: EntityFlags) {
+        self.flags = flags.bits();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.type_idx == NO_ESH
+    }
+
+    // turn entity into empty slot, keeping ids of other entities intact
+    pub fn clear(&mut self) {
+        self.flags = NO_FLAGS;
+        self.type_idx = NO_ESH;
+        self.esh = EntityESH::None;
+    }
+
+    pub fn has_esh(&self) -> bool {
+        !matches!(self.esh, EntityESH::None)
+    }
+
+    // ESH properties that reference another entity
+    pub fn get_references(&self) -> Result<Vec<(&FString, usize)>, FE> {
+        let esh = match self.esh()? {
+            Some(esh) => esh,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(esh
+            .props
+            .iter()
+            .filter_map(|(name, value)| match value {
+                ESHValue::EntityFlags(eflags) => Some((name, eflags.entity_id as usize)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // None for empty slot, Err only if lazy ESH fails to decode
+    pub fn esh(&self) -> Result<Option<&ESH>, FE> {
         match &self.esh {
-            Some(esh) => Ok(esh),
-            None => Err(FE::EntityNoESH),
+            EntityESH::None => Ok(None),
+            EntityESH::Lazy(raw, cell) => {
+                if cell.get().is_none() {
+                    let _ = cell.set(raw.decode()?);
+                }
+                Ok(cell.get())
+            }
+            EntityESH::Decoded(esh) => Ok(Some(esh)),
         }
     }
 
+    pub fn get_esh(&self) -> Result<&ESH, FE> {
+        self.esh()?.ok_or(FE::EntityNoESH)
+    }
+
     pub fn get_esh_mut(&mut self) -> Result<&mut ESH, FE> {
+        if let EntityESH::Lazy(raw, cell) = &mut self.esh {
+            let esh = match cell.take() {
+                Some(esh) => esh,
+                None => raw.decode()?,
+            };
+            self.esh = EntityESH::Decoded(esh);
+        }
+
         match &mut self.esh {
-            Some(esh) => Ok(esh),
-            None => Err(FE::EntityNoESH),
+            EntityESH::Decoded(esh) => Ok(esh),
+            _ => Err(FE::EntityNoESH),
+        }
+    }
+
+    pub fn remap_references(&mut self, remap: impl Fn(usize) -> usize) -> Result<(), FE> {
+        if !self.has_esh() {
+            return Ok(());
+        }
+
+        for value in self.get_esh_mut()?.props.values_mut() {
+            if let ESHValue::EntityFlags(eflags) = value {
+                eflags.entity_id = remap(eflags.entity_id as usize) as u16;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_esh(&self, wd: &mut WriteStream) -> Result<(), FE> {
+        match &self.esh {
+            EntityESH::None => Err(FE::EntityNoESH),
+            EntityESH::Lazy(raw, _) => {
+                wd.write_bytes(raw.as_bytes());
+                Ok(())
+            }
+            EntityESH::Decoded(esh) => wd.write(esh),
+        }
+    }
+
+    // moves entity by changing its first Frame value
+    pub fn set_position(&mut self, a: f32, b: f32, c: f32) -> Result<(), FE> {
+        for value in self.get_esh_mut()?.props.values_mut() {
+            if let ESHValue::Frame(frame) = value {
+                frame.a = a;
+                frame.b = b;
+                frame.c = c;
+                return Ok(());
+            }
         }
+        Err(FE::NoESHValue)
     }
 
     pub fn get_attributes(&self) -> Result<Attributes, FE> {
@@ -37,7 +169,7 @@ impl Entity {
         };
 
         if let ESHValue::Binary(bin) = value {
-            Ok(Attributes::from_binary(&bin)?)
+            Ok(Attributes::from_binary(bin)?)
         } else {
             Err(FE::AttributesNonBinary)
         }
@@ -57,7 +189,7 @@ impl Entity {
         };
 
         if let ESHValue::Binary(bin) = value {
-            Ok(Attributes::from_binary(&bin)?)
+            Ok(Attributes::from_binary(bin)?)
         } else {
             Err(FE::AttributesNonBinary)
         }
@@ -78,22 +210,34 @@ impl DecoderCtx<&mut EntityList, &EntityList> for Entity {
             EntityEncoding::File => {
                 let flags = NO_FLAGS;
                 let type_idx = ctx.add_or_get_type(rd.read()?);
-                let esh: ESH = rd.read()?;
+                let esh: ESH = rd.read().map_err(|fe| fe.context("ESH", rd.offset()))?;
                 let enc_size = rd.offset() - offset;
                 Entity {
                     flags,
                     type_idx,
-                    esh: Some(esh),
+                    esh: EntityESH::Decoded(esh),
                     enc_size,
                 }
             }
             EntityEncoding::World => {
                 let flags = rd.read_u32()?;
                 let type_idx = rd.read_u16()? as usize;
-                let esh: Option<ESH> = if type_idx != NO_ESH {
-                    Some(rd.read()?)
-                } else {
-                    None
+                let esh = match rd.shared() {
+                    _ if type_idx == NO_ESH => EntityESH::None,
+                    Some(data) if rd.is_lazy() => {
+                        let esh_offset = rd.offset();
+                        ESH::skip(rd).map_err(|fe| fe.context("ESH", rd.offset()))?;
+                        let raw = RawESH {
+                            data,
+                            offset: esh_offset,
+                            size: rd.offset() - esh_offset,
+                            codepage: rd.codepage(),
+                        };
+                        EntityESH::Lazy(raw, OnceCell::new())
+                    }
+                    _ => {
+                        EntityESH::Decoded(rd.read().map_err(|fe| fe.context("ESH", rd.offset()))?)
+                    }
                 };
 
                 let enc_size = rd.offset() - offset;
@@ -111,14 +255,13 @@ impl DecoderCtx<&mut EntityList, &EntityList> for Entity {
         match ctx.get_entity_encoding() {
             EntityEncoding::File => {
                 wd.write(ctx.get_type_name(self.type_idx))?;
-                wd.write(self.esh.as_ref().unwrap())?;
+                self.write_esh(wd)?;
             }
             EntityEncoding::World => {
                 wd.write_u32(self.flags)?;
                 wd.write_u16(self.type_idx as u16)?;
-                match self.esh.as_ref() {
-                    Some(esh) => wd.write(esh)?,
-                    None => (),
+                if self.has_esh() {
+                    self.write_esh(wd)?;
                 }
             }
         }
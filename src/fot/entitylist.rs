@@ -1,9 +1,12 @@
 use super::decoder::{Decoder, DecoderCtx};
-use super::entity::Entity;
+use super::entity::{Entity, NO_ESH};
 use super::ferror::FError as FE;
-use super::fstring::{FString, FStringEncoding};
+use super::fstring::{CodePage, FString, FStringEncoding};
+use super::inventory;
+use super::raw::Raw;
 use super::stream::{ReadStream, WriteStream};
 use super::tag::{CTag, Tag};
+use std::collections::HashMap;
 use std::path::Path;
 
 #[derive(Clone, Copy, PartialEq)]
@@ -12,7 +15,7 @@ pub enum EntityEncoding {
     World,
 }
 
-const DEFAULT_ENTITY_TAG: CTag<'static> = CTag {
+pub const DEFAULT_ENTITY_TAG: CTag<'static> = CTag {
     name: "<entity>",
     version: "2",
 };
@@ -29,6 +32,10 @@ pub struct EntityList {
 }
 
 impl EntityList {
+    // World encoding stores entity count + 1 as u16,
+    // and 0xFFFF is reserved as NO_ESH
+    const MAX_ENTITIES: usize = NO_ESH - 2;
+
     pub fn get_entity_encoding(&self) -> EntityEncoding {
         self.encoding
     }
@@ -37,6 +44,11 @@ impl EntityList {
         self.entity_tag.as_ref().unwrap()
     }
 
+    // <entity_file> tag of world, and <entity> tag of entities in .ent file
+    pub fn get_tags(&self) -> (Option<&Tag>, Option<&Tag>) {
+        (self.entity_file_tag.as_ref(), self.entity_tag.as_ref())
+    }
+
     pub fn add_new_type(&mut self, type_name: FString) -> usize {
         self.types.push(type_name);
         self.types.len() - 1
@@ -57,23 +69,195 @@ impl EntityList {
         &self.types[type_idx]
     }
 
-    pub fn get_entity(&self, id: usize) -> &Entity {
-        &self.ents[id - 1]
+    pub fn get_types(&self) -> &[FString] {
+        &self.types
+    }
+
+    // number of entities of each type, indexed by type_idx
+    pub fn count_types(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.types.len()];
+        for ent in self.ents.iter() {
+            if !ent.is_empty() {
+                counts[ent.type_idx] += 1;
+            }
+        }
+        counts
+    }
+
+    // drop types that no entity uses, returns number of dropped types
+    pub fn remove_unused_types(&mut self) -> usize {
+        let counts = self.count_types();
+        let mut remap: Vec<usize> = Vec::with_capacity(self.types.len());
+        let mut types: Vec<FString> = Vec::with_capacity(self.types.len());
+        for (type_name, count) in self.types.drain(..).zip(counts) {
+            remap.push(types.len());
+            if count > 0 {
+                types.push(type_name);
+            }
+        }
+
+        let removed = remap.len() - types.len();
+        self.types = types;
+        for ent in self.ents.iter_mut() {
+            if !ent.is_empty() {
+                ent.type_idx = remap[ent.type_idx];
+            }
+        }
+        removed
     }
 
-    pub fn get_entity_mut(&mut self, id: usize) -> &mut Entity {
-        &mut self.ents[id - 1]
+    // entity ids are indexed from 1
+    pub fn get_entity(&self, id: usize) -> Result<&Entity, FE> {
+        match id.checked_sub(1).and_then(|idx| self.ents.get(idx)) {
+            Some(ent) => Ok(ent),
+            None => Err(FE::NoEntity(id)),
+        }
+    }
+
+    pub fn get_entity_mut(&mut self, id: usize) -> Result<&mut Entity, FE> {
+        match id.checked_sub(1).and_then(|idx| self.ents.get_mut(idx)) {
+            Some(ent) => Ok(ent),
+            None => Err(FE::NoEntity(id)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ents.is_empty()
+    }
+
+    // appends entity with next free id, type_name is mapped into types table
+    pub fn push(&mut self, type_name: FString, mut ent: Entity) -> Result<usize, FE> {
+        if self.ents.len() >= Self::MAX_ENTITIES {
+            return Err(FE::TooManyEntities);
+        }
+
+        ent.type_idx = self.add_or_get_type(type_name);
+        self.ents.push(ent);
+        Ok(self.ents.len())
+    }
+
+    // clone entity together with entities it owns, so clones don't share
+    // items with originals. Links between cloned entities are remapped to
+    // clones, links to anything else are kept. Returns id of entity's clone
+    pub fn clone_entity(&mut self, id: usize) -> Result<usize, FE> {
+        if !self.get_entity(id)?.has_esh() {
+            return Err(FE::EntityNoESH);
+        }
+
+        let ids = self.collect_dependencies(id)?;
+        let mut remap: HashMap<usize, usize> = HashMap::with_capacity(ids.len());
+        for &old_id in &ids {
+            let ent = self.get_entity(old_id)?;
+            let type_name = self.get_type_name(ent.type_idx).clone();
+            let ent = ent.clone();
+            remap.insert(old_id, self.push(type_name, ent)?);
+        }
+
+        for &new_id in remap.values() {
+            self.get_entity_mut(new_id)?
+                .remap_references(|target| *remap.get(&target).unwrap_or(&target))?;
+        }
+
+        Ok(remap[&id])
+    }
+
+    pub fn is_valid_id(&self, id: usize) -> bool {
+        self.get_entity(id).is_ok_and(|ent| !ent.is_empty())
+    }
+
+    // entity id and ids of every entity it owns, directly or through owned entities
+    pub fn collect_dependencies(&self, id: usize) -> Result<Vec<usize>, FE> {
+        let mut ids: Vec<usize> = vec![id];
+        let mut i = 0;
+        while i < ids.len() {
+            for target in inventory::get_owned(self, ids[i])? {
+                if self.is_valid_id(target) && !ids.contains(&target) {
+                    ids.push(target);
+                }
+            }
+            i += 1;
+        }
+        Ok(ids)
+    }
+
+    // copy entities from another list with fresh ids, links between copied
+    // entities are remapped and links to anything else are cleared
+    pub fn transfer_entities(
+        &mut self,
+        from: &EntityList,
+        ids: &[usize],
+    ) -> Result<HashMap<usize, usize>, FE> {
+        let mut remap: HashMap<usize, usize> = HashMap::with_capacity(ids.len());
+        for &id in ids {
+            remap.insert(id, self.import_entity(from, id)?);
+        }
+
+        for &new_id in remap.values() {
+            self.get_entity_mut(new_id)?
+                .remap_references(|target| *remap.get(&target).unwrap_or(&0))?;
+        }
+
+        Ok(remap)
+    }
+
+    // entities and their properties that still point to entity id
+    pub fn find_references(&self, id: usize) -> Result<Vec<(usize, &FString)>, FE> {
+        let mut refs: Vec<(usize, &FString)> = Vec::new();
+        for (ref_id, ent) in self {
+            for (name, target) in ent.get_references()? {
+                if target == id {
+                    refs.push((ref_id, name));
+                }
+            }
+        }
+        Ok(refs)
+    }
+
+    // ids are positional, so removed entity becomes empty NO_ESH slot
+    pub fn remove_entity(&mut self, id: usize) -> Result<(), FE> {
+        self.get_entity_mut(id)?.clear();
+        Ok(())
+    }
+
+    // copy entity from another list (i.e. loaded .ent file) with fresh id
+    pub fn import_entity(&mut self, from: &EntityList, id: usize) -> Result<usize, FE> {
+        let ent = from.get_entity(id)?;
+        if !ent.has_esh() {
+            return Err(FE::EntityNoESH);
+        }
+
+        // new type names should be encoded like rest of types table
+        let mut type_name = from.get_type_name(ent.type_idx).clone();
+        if let Some(first) = self.types.first() {
+            type_name.set_encoding(first.encoding(), first.codepage());
+        }
+
+        self.push(type_name, ent.clone())
+    }
+
+    pub fn load_entfile(path: &Path, codepage: CodePage) -> Result<EntityList, FE> {
+        let raw = Raw::load_file(path)?;
+        let mut rd = ReadStream::new(&raw.mem, 0).with_codepage(codepage);
+        rd.read_ctx(EntityEncoding::File)
+    }
+
+    pub fn save_entfile(&self, path: &Path) -> Result<(), FE> {
+        let mut wd = WriteStream::new(self.get_enc_size());
+        wd.write_ctx(self, EntityEncoding::File)?;
+        wd.into_raw(0, 0).dump(path)?;
+        Ok(())
     }
 
     pub fn dump_to_entfile(&self, ent: &Entity, path: &Path) -> Result<(), FE> {
-        let esh = match &ent.esh {
-            Some(esh) => esh,
-            None => return Err(FE::EntityNoESH),
-        };
+        let esh = ent.get_esh()?;
 
         let tag = DEFAULT_ENTITY_TAG.to_tag();
         let mut type_name = self.get_type_name(ent.type_idx).clone();
-        type_name.encoding = FStringEncoding::ANSI;
+        type_name.set_encoding(FStringEncoding::ANSI, type_name.codepage());
 
         let mut wd = WriteStream::new(tag.get_enc_size() + ent.get_enc_size());
         wd.write(&tag)?;
@@ -108,7 +292,9 @@ impl DecoderCtx<EntityEncoding, EntityEncoding> for EntityList {
                         first = false;
                     }
 
-                    let ent: Entity = rd.read_ctx(&mut ent_list)?;
+                    let ent: Entity = rd.read_ctx(&mut ent_list).map_err(|fe| {
+                        fe.context(format!("entity {}", ent_list.len() + 1), rd.offset())
+                    })?;
                     ent_list.ents.push(ent);
                 }
 
@@ -119,14 +305,25 @@ impl DecoderCtx<EntityEncoding, EntityEncoding> for EntityList {
             EntityEncoding::World => {
                 ent_list.entity_file_tag = Some(rd.read()?);
                 let type_count = rd.read_u32()?;
-                for _ in 0..type_count {
-                    ent_list.types.push(rd.read()?);
+                for i in 0..type_count {
+                    let type_name = rd
+                        .read()
+                        .map_err(|fe| fe.context(format!("type {}", i), rd.offset()))?;
+                    ent_list.types.push(type_name);
                 }
 
                 let ent_count = rd.read_u16()?;
                 ent_list.unk1 = rd.read_u32()?;
                 for _ in 1..ent_count {
-                    let ent: Entity = rd.read_ctx(&mut ent_list)?;
+                    let ent_offset = rd.offset();
+                    let ent: Entity = rd.read_ctx(&mut ent_list).map_err(|fe| {
+                        fe.context(format!("entity {}", ent_list.len() + 1), rd.offset())
+                    })?;
+                    // type index has to point into types table
+                    if !ent.is_empty() && ent.type_idx >= ent_list.types.len() {
+                        return Err(FE::NoType(ent.type_idx)
+                            .context(format!("entity {}", ent_list.len() + 1), ent_offset));
+                    }
                     ent_list.ents.push(ent);
                 }
 
@@ -140,11 +337,11 @@ impl DecoderCtx<EntityEncoding, EntityEncoding> for EntityList {
         match ctx {
             EntityEncoding::File => {
                 for ent in self.ents.iter() {
-                    if ent.esh.is_none() {
+                    if !ent.has_esh() {
                         continue;
                     }
                     wd.write(self.get_entity_tag())?;
-                    wd.write_ctx(ent, &self)?;
+                    wd.write_ctx(ent, self)?;
                 }
             }
             EntityEncoding::World => {
@@ -157,7 +354,7 @@ impl DecoderCtx<EntityEncoding, EntityEncoding> for EntityList {
                 wd.write_u16((self.ents.len() + 1) as u16)?;
                 wd.write_u32(self.unk1)?;
                 for ent in self.ents.iter() {
-                    wd.write_ctx(ent, &self)?;
+                    wd.write_ctx(ent, self)?;
                 }
             }
         }
@@ -1,18 +1,19 @@
 use super::decoder::Decoder;
 use super::ferror::FError as FE;
-use super::fstring::FString;
+use super::flags::LinkFlags;
+use super::fstring::{CodePage, FString, FStringEncoding};
 use super::stream::{ReadStream, WriteStream};
 use super::tag::Tag;
 use indexmap::IndexMap;
 use std::fmt;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ESHUnknown {
     pub data_type: u32,
     pub data: Vec<u8>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ESHEntityFlags {
     pub entity_id: u16,
     pub flags: u16,
@@ -20,9 +21,17 @@ pub struct ESHEntityFlags {
 
 impl ESHEntityFlags {
     const SIZE: usize = 4;
+
+    pub fn get_flags(&self) -> LinkFlags {
+        LinkFlags::from_bits_retain(self.flags)
+    }
+
+    pub fn set_flags(&mut self, flags: LinkFlags) {
+        self.flags = flags.bits();
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ESHFrame {
     pub unk1: Vec<u8>,
     pub a: f32,
@@ -34,7 +43,7 @@ impl ESHFrame {
     const SIZE: usize = 48;
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ESHRect {
     pub top: i32,
     pub left: i32,
@@ -46,7 +55,7 @@ impl ESHRect {
     const SIZE: usize = 16;
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ESHValue {
     Unknown(ESHUnknown),
     Bool(bool),
@@ -78,46 +87,8 @@ impl ESHValue {
 
 impl Decoder for ESHValue {
     fn decode<'a>(rd: &mut ReadStream<'a>) -> Result<Self, FE> {
-        let data_type = rd.read_u32()?;
-        let data_size = rd.read_u32()?;
-
-        Ok(match data_type {
-            Self::TYPE_BOOL => ESHValue::Bool(rd.read_u8()? == 1),
-            Self::TYPE_FLOAT => ESHValue::Float(rd.read_f32()?),
-            Self::TYPE_INT => ESHValue::Int(rd.read_i32()?),
-            Self::TYPE_STRING => ESHValue::String(rd.read::<FString>()?),
-            Self::TYPE_SPRITE => ESHValue::Sprite(rd.read::<FString>()?),
-            Self::TYPE_ENUM => ESHValue::Enum(rd.read::<FString>()?),
-            Self::TYPE_ESBIN => ESHValue::Binary(rd.read_bytes(data_size as usize)?),
-            Self::TYPE_ENTTITYFLAGS => {
-                let entity_id = rd.read_u16()?;
-                let flags = rd.read_u16()?;
-                ESHValue::EntityFlags(ESHEntityFlags { entity_id, flags })
-            }
-            Self::TYPE_FRAME => {
-                let unk1 = rd.read_bytes(0x24)?;
-                let c = rd.read_f32()? * 4.;
-                let b = rd.read_f32()? * 4.;
-                let a = rd.read_f32()? * 4.;
-                ESHValue::Frame(ESHFrame { unk1, a, b, c })
-            }
-            Self::TYPE_RECT => {
-                let top = rd.read_i32()?;
-                let left = rd.read_i32()?;
-                let right = rd.read_i32()?;
-                let bottom = rd.read_i32()?;
-                ESHValue::Rect(ESHRect {
-                    top,
-                    left,
-                    right,
-                    bottom,
-                })
-            }
-            _ => {
-                let data = rd.read_bytes(data_size as usize)?;
-                ESHValue::Unknown(ESHUnknown { data_type, data })
-            }
-        })
+        let (data_type, data) = Self::read_raw(rd)?;
+        Self::from_raw(data_type, data, rd.codepage())
     }
 
     fn encode(&self, wd: &mut WriteStream) -> Result<(), FE> {
@@ -232,7 +203,7 @@ impl fmt::Display for ESHValue {
             ESHValue::Enum(enm) => write!(f, "{}", enm),
             ESHValue::Binary(bin) => write!(f, "Binary, size {}", bin.len()),
             ESHValue::EntityFlags(val) => {
-                write!(f, "entity {} flags {:x}", val.entity_id, val.flags)
+                write!(f, "entity {} flags {}", val.entity_id, val.get_flags())
             }
             ESHValue::Frame(val) => {
                 write!(f, "[{},{},{}]", val.a, val.b, val.c)
@@ -248,14 +219,94 @@ impl fmt::Display for ESHValue {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ESH {
     pub tag: Tag,
     pub props: IndexMap<FString, ESHValue>,
-    enc_size: usize,
+    codepage: CodePage,
+}
+
+impl ESHValue {
+    // type and data of encoded value. Value always ends where its data size
+    // says, so decoding and skipping ESH move over the same bytes
+    fn read_raw<'a>(rd: &mut ReadStream<'a>) -> Result<(u32, &'a [u8]), FE> {
+        let data_type = rd.read_u32()?;
+        let data_size = rd.read_u32()? as usize;
+        Ok((data_type, rd.as_bytes(data_size)?))
+    }
+
+    // value is read only from its own data
+    fn from_raw(data_type: u32, data: &[u8], codepage: CodePage) -> Result<Self, FE> {
+        let mut rd = ReadStream::new(data, 0).with_codepage(codepage);
+        Ok(match data_type {
+            Self::TYPE_BOOL => ESHValue::Bool(rd.read_u8()? == 1),
+            Self::TYPE_FLOAT => ESHValue::Float(rd.read_f32()?),
+            Self::TYPE_INT => ESHValue::Int(rd.read_i32()?),
+            Self::TYPE_STRING => ESHValue::String(rd.read::<FString>()?),
+            Self::TYPE_SPRITE => ESHValue::Sprite(rd.read::<FString>()?),
+            Self::TYPE_ENUM => ESHValue::Enum(rd.read::<FString>()?),
+            Self::TYPE_ESBIN => ESHValue::Binary(data.to_vec()),
+            Self::TYPE_ENTTITYFLAGS => {
+                let entity_id = rd.read_u16()?;
+                let flags = rd.read_u16()?;
+                ESHValue::EntityFlags(ESHEntityFlags { entity_id, flags })
+            }
+            Self::TYPE_FRAME => {
+                let unk1 = rd.read_bytes(0x24)?;
+                let c = rd.read_f32()? * 4.;
+                let b = rd.read_f32()? * 4.;
+                let a = rd.read_f32()? * 4.;
+                ESHValue::Frame(ESHFrame { unk1, a, b, c })
+            }
+            Self::TYPE_RECT => {
+                let top = rd.read_i32()?;
+                let left = rd.read_i32()?;
+                let right = rd.read_i32()?;
+                let bottom = rd.read_i32()?;
+                ESHValue::Rect(ESHRect {
+                    top,
+                    left,
+                    right,
+                    bottom,
+                })
+            }
+            _ => ESHValue::Unknown(ESHUnknown {
+                data_type,
+                data: data.to_vec(),
+            }),
+        })
+    }
 }
 
+// encoding and bytes of property name
+type RawName<'a> = (FStringEncoding, &'a [u8]);
+
 impl ESH {
+    // reads tag and every property, passing its raw name and value to f.
+    // decode and skip both walk ESH with it, so they can't disagree on size
+    fn read_props<'a>(
+        rd: &mut ReadStream<'a>,
+        mut f: impl FnMut(RawName<'a>, u32, &'a [u8]) -> Result<(), FE>,
+    ) -> Result<Tag, FE> {
+        let tag: Tag = rd.read()?;
+        let codepage = rd.codepage();
+
+        let n = rd.read_u32()? as usize;
+        for i in 0..n {
+            let (encoding, name) = FString::read_raw(rd)
+                .map_err(|fe| fe.context(format!("name {}", i), rd.offset()))?;
+            let offset = rd.offset();
+            // name is decoded for context only when value fails
+            let context = |fe: FE| {
+                let name = FString::from_raw(encoding, codepage, name);
+                fe.context(format!("\"{}\"", name), offset)
+            };
+            let (data_type, data) = ESHValue::read_raw(rd).map_err(context)?;
+            f((encoding, name), data_type, data).map_err(context)?;
+        }
+        Ok(tag)
+    }
+
     pub fn get(&self, name: &str) -> Option<&ESHValue> {
         self.props.get(name)
     }
@@ -271,15 +322,22 @@ impl ESH {
         };
 
         if let ESHValue::Binary(bin) = value {
-            let mut rd = ReadStream::new(bin, 0);
+            let mut rd = ReadStream::new(bin, 0).with_codepage(self.codepage);
 
             let _ = rd.read_u32()?;
             rd.read::<ESH>()
+                .map_err(|fe| fe.context(format!("nested \"{}\"", name), rd.offset()))
         } else {
             Err(FE::ESHValueNonBinary)
         }
     }
 
+    // moves over encoded ESH without decoding names and values
+    pub fn skip(rd: &mut ReadStream<'_>) -> Result<(), FE> {
+        Self::read_props(rd, |_, _, _| Ok(()))?;
+        Ok(())
+    }
+
     pub fn set_nested(&mut self, name: &str, value: ESH) -> Result<(), FE> {
         let mut wd = WriteStream::new(4 + value.get_enc_size());
         wd.write_u32(value.get_enc_size() as u32)?;
@@ -292,22 +350,18 @@ impl ESH {
 
 impl Decoder for ESH {
     fn decode<'a>(rd: &mut ReadStream<'a>) -> Result<Self, FE> {
-        let offset = rd.offset();
-        let tag: Tag = rd.read()?;
-
-        let n = rd.read_u32()? as usize;
-        let mut props: IndexMap<FString, ESHValue> = IndexMap::with_capacity(n);
-        for _ in 0..n {
-            let name: FString = rd.read()?;
-            let value: ESHValue = rd.read()?;
-            props.insert(name, value);
-        }
+        let codepage = rd.codepage();
+        let mut props: IndexMap<FString, ESHValue> = IndexMap::new();
+        let tag = Self::read_props(rd, |(encoding, name), data_type, data| {
+            let value = ESHValue::from_raw(data_type, data, codepage)?;
+            props.insert(FString::from_raw(encoding, codepage, name), value);
+            Ok(())
+        })?;
 
-        let enc_size = rd.offset() - offset;
         Ok(ESH {
             tag,
             props,
-            enc_size,
+            codepage: rd.codepage(),
         })
     }
 
@@ -323,7 +377,83 @@ impl Decoder for ESH {
         Ok(())
     }
 
+    // derived from current props, since they may be edited after decoding
     fn get_enc_size(&self) -> usize {
-        self.enc_size
+        self.tag.get_enc_size()
+            + 4
+            + self
+                .props
+                .iter()
+                .map(|(name, value)| name.get_enc_size() + value.get_enc_size())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::tests::round_trip;
+    use super::*;
+
+    fn prop(data: &mut Vec<u8>, name: &str, data_type: u32, value: &[u8]) {
+        data.extend((name.len() as u32).to_le_bytes());
+        data.extend(name.as_bytes());
+        data.extend(data_type.to_le_bytes());
+        data.extend((value.len() as u32).to_le_bytes());
+        data.extend(value);
+    }
+
+    fn esh(props: &[(&str, u32, &[u8])]) -> Vec<u8> {
+        let mut data: Vec<u8> = b"<esh>\x002\0".to_vec();
+        data.extend((props.len() as u32).to_le_bytes());
+        for (name, data_type, value) in props {
+            prop(&mut data, name, *data_type, value);
+        }
+        data
+    }
+
+    // binary value with nested ESH, prefixed by its size
+    fn nested(esh: &[u8]) -> Vec<u8> {
+        let mut data = (esh.len() as u32).to_le_bytes().to_vec();
+        data.extend(esh);
+        data
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        let bytes = esh(&[
+            ("hitPoints", ESHValue::TYPE_INT, &25i32.to_le_bytes()),
+            ("Name", ESHValue::TYPE_STRING, b"\x02\0\0\0Jo"),
+            ("Owner", ESHValue::TYPE_ENTTITYFLAGS, &[1, 0, 0x10, 0]),
+        ]);
+        let esh: ESH = round_trip(&bytes);
+        assert_eq!(esh.get("hitPoints"), Some(&ESHValue::Int(25)));
+        assert_eq!(
+            esh.get("Owner"),
+            Some(&ESHValue::EntityFlags(ESHEntityFlags {
+                entity_id: 1,
+                flags: 0x10
+            }))
+        );
+    }
+
+    #[test]
+    fn set_nested_after_edit() {
+        let inner = esh(&[("Name", ESHValue::TYPE_STRING, b"\x02\0\0\0Jo")]);
+        let mut outer: ESH = round_trip(&esh(&[("Inner", ESHValue::TYPE_ESBIN, &nested(&inner))]));
+
+        let mut value = outer.get_nested("Inner").unwrap();
+        match value.props.get_mut("Name") {
+            Some(ESHValue::String(name)) => name.set_str("Joanna".to_string()),
+            _ => panic!("no Name"),
+        }
+        outer.set_nested("Inner", value).unwrap();
+
+        // size prefix follows edited string, not decoded size
+        let edited = esh(&[("Name", ESHValue::TYPE_STRING, b"\x06\0\0\0Joanna")]);
+        assert_eq!(outer.get("Inner"), Some(&ESHValue::Binary(nested(&edited))));
+        assert_eq!(
+            outer.get_nested("Inner").unwrap().get_enc_size(),
+            edited.len()
+        );
     }
 }
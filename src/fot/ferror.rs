@@ -13,21 +13,83 @@ pub enum FError {
     ESHValueNonBinary,
     AttributesNonBinary,
     ValueNoESBIN,
+    NoAttributeGroup(String),
+    NoAttribute(String),
+    InvalidValue(String),
+    TemplateSyntax(usize),
+    TooManyEntities,
+    NotInInventory(usize, usize),
+    NoInventory(usize),
+    NoFreeItemLink(usize),
+    UnknownFlag(String),
+    NoEntity(usize),
+    NoType(usize),
+    DialogSyntax(usize),
+    NoDialogLine(String, usize),
+    SGDCountMismatch(usize, usize),
+    NoEntitySelector,
+    NoMatchingEntities,
+    NotSingleEntity(usize),
+    // output path and error that stopped writing
+    WriteFailed(String, Box<FError>),
+    // decode path, offset where decoding stopped and original error
+    Decode(Vec<String>, DecodeOffset, Box<FError>),
+}
+
+// offset in file, or in uncompressed world data for structures inside World
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeOffset {
+    File(usize),
+    World(usize),
+}
+
+impl std::fmt::Display for DecodeOffset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeOffset::File(offset) => write!(f, "offset {:#x}", offset),
+            DecodeOffset::World(offset) => write!(f, "world+{:#x}", offset),
+        }
+    }
+}
+
+impl FError {
+    // prepend structure name to decode path. Innermost structure sets
+    // offset, outer ones only extend path
+    pub fn context(self, name: impl Into<String>, offset: usize) -> FError {
+        match self {
+            FError::Decode(mut path, offset, source) => {
+                path.insert(0, name.into());
+                FError::Decode(path, offset, source)
+            }
+            fe => FError::Decode(vec![name.into()], DecodeOffset::File(offset), Box::new(fe)),
+        }
+    }
+
+    // context of structure inside World, offsets up to it are in
+    // uncompressed world data
+    pub fn world_context(self, name: impl Into<String>, offset: usize) -> FError {
+        match self.context(name, offset) {
+            FError::Decode(path, DecodeOffset::File(offset), source) => {
+                FError::Decode(path, DecodeOffset::World(offset), source)
+            }
+            fe => fe,
+        }
+    }
 }
 
 impl std::fmt::Display for FError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use FError as FE;
         match self {
-            FE::IOError(e) => write!(f, "IOError {}", e),
-            FE::Utf8Error(e) => write!(f, "Utf8Error {}", e),
+            FE::IOError(_) => write!(f, "IO error"),
+            FE::Utf8Error(_) => write!(f, "Invalid UTF-8"),
             FE::DeflateError(e) => write!(f, "DeflateError {}", e),
             FE::NoWorld => write!(f, "No world found in file"),
             FE::NoCampaign => write!(f, "No campaign found after world"),
             FE::UnknownWorldSize => write!(f, "Unable to determine world block size"),
             FE::StreamOverflow(offset, size, read) => write!(
                 f,
-                "stream read {} at offset {} overflow size {}",
+                "stream read {} at offset {:#x} overflow size {:#x}",
                 read, offset, size
             ),
             FE::NoZeroTerminator => write!(f, "No zero-terminator when String::decode"),
@@ -36,6 +98,52 @@ impl std::fmt::Display for FError {
             FE::ESHValueNonBinary => write!(f, "ESH value is not binary"),
             FE::AttributesNonBinary => write!(f, "Attributes Binary != true"),
             FE::ValueNoESBIN => write!(f, "Value has no esbin"),
+            FE::NoAttributeGroup(group) => write!(f, "No attribute group {}", group),
+            FE::NoAttribute(name) => write!(f, "No attribute {}", name),
+            FE::InvalidValue(value) => write!(f, "Invalid value {}", value),
+            FE::TemplateSyntax(line) => write!(f, "Template syntax error at line {}", line),
+            FE::TooManyEntities => write!(f, "Entity count exceeds u16 limit"),
+            FE::UnknownFlag(flag) => write!(f, "Invalid flag {}, expected 0x hex mask", flag),
+            FE::NoEntity(id) => write!(f, "No entity with id {}", id),
+            FE::NoType(idx) => write!(f, "No entity type with index {}", idx),
+            FE::DialogSyntax(line) => write!(f, "Dialogs syntax error at line {}", line),
+            FE::NoDialogLine(speaker, index) => {
+                write!(f, "No dialog line {} of speaker {}", index, speaker)
+            }
+            FE::SGDCountMismatch(names, dialogs) => write!(
+                f,
+                "SGD has {} speaker names but {} dialog lists",
+                names, dialogs
+            ),
+            FE::NoEntitySelector => {
+                write!(f, "No entity selector provided (--ids, --find or --type)")
+            }
+            FE::NoMatchingEntities => write!(f, "No entities match selection"),
+            FE::NotSingleEntity(count) => {
+                write!(f, "Expected exactly one entity, but {} selected", count)
+            }
+            FE::WriteFailed(path, _) => write!(f, "Failed to write {}", path),
+            FE::NotInInventory(item, owner) => {
+                write!(f, "Entity {} is not in inventory of {}", item, owner)
+            }
+            FE::NoInventory(owner) => {
+                write!(f, "Entity {} has no Inventory link", owner)
+            }
+            FE::NoFreeItemLink(container) => {
+                write!(f, "Container {} has no empty item link", container)
+            }
+            FE::Decode(path, offset, _) => write!(f, "{} at {}", path.join(" > "), offset),
+        }
+    }
+}
+
+impl std::error::Error for FError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FError::IOError(e) => Some(e),
+            FError::Utf8Error(e) => Some(e),
+            FError::WriteFailed(_, source) | FError::Decode(_, _, source) => Some(source.as_ref()),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,92 @@
+use bitflags::{bitflags, Flags};
+use std::fmt;
+
+// Meaning of single flags is not known, so they have no names. Flags are
+// set and cleared by 0x prefixed hex mask and printed as raw hex
+
+bitflags! {
+    // Entity::flags from world's entity list
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct EntityFlags: u32 {
+        const _ = !0;
+    }
+
+    // ESHEntityFlags::flags of entity links
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct LinkFlags: u16 {
+        const _ = !0;
+    }
+}
+
+impl fmt::Display for EntityFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x}", self.bits())
+    }
+}
+
+impl fmt::Display for LinkFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x}", self.bits())
+    }
+}
+
+// parse flag mask as 0x prefixed hex number
+pub fn parse_flag<F: Flags>(mask: &str) -> Option<F>
+where
+    F::Bits: TryFrom<u32>,
+{
+    let hex = mask.strip_prefix("0x")?;
+    let bits = u32::from_str_radix(hex, 16).ok()?;
+    Some(F::from_bits_retain(F::Bits::try_from(bits).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_flag_hex() {
+        assert_eq!(
+            parse_flag::<EntityFlags>("0x2").map(|f| f.bits()),
+            Some(0x2)
+        );
+        assert_eq!(
+            parse_flag::<EntityFlags>("0x80000000").map(|f| f.bits()),
+            Some(0x80000000)
+        );
+        assert_eq!(
+            parse_flag::<LinkFlags>("0xffff").map(|f| f.bits()),
+            Some(0xffff)
+        );
+        // too wide for link flags
+        assert_eq!(parse_flag::<LinkFlags>("0x10000"), None);
+        // hex needs 0x prefix
+        assert_eq!(parse_flag::<EntityFlags>("10"), None);
+        assert_eq!(parse_flag::<EntityFlags>("0X10"), None);
+        assert_eq!(parse_flag::<EntityFlags>("0x"), None);
+        assert_eq!(parse_flag::<EntityFlags>("0xg"), None);
+    }
+
+    #[test]
+    fn parse_flag_no_names() {
+        assert_eq!(parse_flag::<EntityFlags>("DEAD"), None);
+        assert_eq!(parse_flag::<LinkFlags>("hidden"), None);
+        assert_eq!(parse_flag::<EntityFlags>(""), None);
+    }
+
+    #[test]
+    fn set_clear_mask() {
+        let mut flags = EntityFlags::from_bits_retain(0x11);
+        flags.set(parse_flag("0x6").unwrap(), true);
+        assert_eq!(flags.bits(), 0x17);
+        flags.set(parse_flag("0x3").unwrap(), false);
+        assert_eq!(flags.bits(), 0x14);
+    }
+
+    #[test]
+    fn display_raw_hex() {
+        assert_eq!(EntityFlags::empty().to_string(), "0");
+        assert_eq!(EntityFlags::from_bits_retain(0x3).to_string(), "3");
+        assert_eq!(LinkFlags::from_bits_retain(0xe).to_string(), "e");
+    }
+}
@@ -1,74 +1,160 @@
 use super::decoder::Decoder;
 use super::ferror::FError as FE;
 use super::stream::{ReadStream, WriteStream};
-use encoding_rs::WINDOWS_1251;
-use std::borrow::Borrow;
+use encoding_rs::{Encoding, WINDOWS_1251};
+use std::borrow::{Borrow, Cow};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 
 // FString - Fallout
 
-#[derive(Debug, Hash, PartialEq, Eq, Clone)]
+// Code page of ANSI strings depends on game localization:
+// 1251 for Russian, 1252 for Western and 1250 for Central European
+pub type CodePage = &'static Encoding;
+pub const DEFAULT_CODEPAGE: CodePage = WINDOWS_1251;
+
+// accepts encoding label (windows-1252, cp1250) or just code page number
+pub fn codepage_for_name(name: &str) -> Option<CodePage> {
+    Encoding::for_label(name.as_bytes())
+        .or_else(|| Encoding::for_label(format!("windows-{}", name).as_bytes()))
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
 pub enum FStringEncoding {
     ANSI,
     WCS2,
 }
 
+// raw keeps encoded bytes as they were read, so strings that code page
+// can't map are written back unchanged. Setters drop raw, so str, encoding
+// and code page are private and changed only through them
 #[derive(Debug, Eq, Clone)]
 pub struct FString {
-    pub encoding: FStringEncoding,
-    pub enc_len: usize,
-    pub str: String,
+    encoding: FStringEncoding,
+    codepage: CodePage,
+    str: String,
+    raw: Option<Vec<u8>>,
 }
 
-impl Decoder for FString {
-    fn decode<'a>(rd: &mut ReadStream<'a>) -> Result<Self, FE> {
-        //let mut rdr = Cursor::new(&raw.mem[offset..]);
+impl FString {
+    fn decode_bytes(encoding: &FStringEncoding, codepage: CodePage, bytes: &[u8]) -> String {
+        match encoding {
+            FStringEncoding::ANSI => codepage.decode(bytes).0.to_string(),
+            // UTF-16LE
+            FStringEncoding::WCS2 => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.str
+    }
+
+    pub fn encoding(&self) -> FStringEncoding {
+        self.encoding
+    }
+
+    pub fn codepage(&self) -> CodePage {
+        self.codepage
+    }
+
+    pub fn set_str(&mut self, str: String) {
+        self.str = str;
+        self.raw = None;
+    }
+
+    pub fn set_encoding(&mut self, encoding: FStringEncoding, codepage: CodePage) {
+        self.encoding = encoding;
+        self.codepage = codepage;
+        self.raw = None;
+    }
+
+    // bytes and whether code page had to replace unmappable characters
+    fn encode_bytes(&self) -> (Cow<'_, [u8]>, bool) {
+        if let Some(raw) = self.raw.as_deref() {
+            return (Cow::Borrowed(raw), false);
+        }
+
+        match self.encoding {
+            FStringEncoding::ANSI => {
+                let (bytes, _, had_errors) = self.codepage.encode(&self.str);
+                (Cow::Owned(bytes.into_owned()), had_errors)
+            }
+            FStringEncoding::WCS2 => (
+                Cow::Owned(
+                    self.str
+                        .encode_utf16()
+                        .flat_map(|unit| unit.to_le_bytes())
+                        .collect(),
+                ),
+                false,
+            ),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<Cow<'_, [u8]>, FE> {
+        match self.encode_bytes() {
+            (_, true) => Err(FE::InvalidValue(format!(
+                "\"{}\" for code page {}",
+                self.str,
+                self.codepage.name()
+            ))),
+            (bytes, false) => Ok(bytes),
+        }
+    }
+
+    // encoding and size of string bytes
+    fn read_header(rd: &mut ReadStream<'_>) -> Result<(FStringEncoding, usize), FE> {
         let flen = rd.read_u32()? as usize;
         let len = flen & !(1 << 31);
-        if flen & (1 << 31) == 0 {
-            // ANSI
-            let bytes = rd.as_bytes(len)?;
-            let (str, _, _) = WINDOWS_1251.decode(bytes);
-            Ok(FString {
-                encoding: FStringEncoding::ANSI,
-                enc_len: len,
-                str: str.to_string(),
-            })
+        Ok(if flen & (1 << 31) == 0 {
+            (FStringEncoding::ANSI, len)
         } else {
-            // WCS2
-            let bytes = rd.as_bytes(len * 2)?;
-            let chars: Vec<u8> = bytes.iter().step_by(2).copied().collect();
-            let (str, _, _) = WINDOWS_1251.decode(&chars);
-            Ok(FString {
-                encoding: FStringEncoding::WCS2,
-                enc_len: len,
-                str: str.to_string(),
-            })
+            // WCS2 length is in UTF-16 code units
+            (FStringEncoding::WCS2, len * 2)
+        })
+    }
+
+    // encoding and string bytes, moving over them without decoding
+    pub fn read_raw<'a>(rd: &mut ReadStream<'a>) -> Result<(FStringEncoding, &'a [u8]), FE> {
+        let (encoding, size) = Self::read_header(rd)?;
+        Ok((encoding, rd.as_bytes(size)?))
+    }
+
+    pub fn from_raw(encoding: FStringEncoding, codepage: CodePage, bytes: &[u8]) -> Self {
+        FString {
+            str: Self::decode_bytes(&encoding, codepage, bytes),
+            encoding,
+            codepage,
+            raw: Some(bytes.to_vec()),
         }
     }
+}
+
+impl Decoder for FString {
+    fn decode<'a>(rd: &mut ReadStream<'a>) -> Result<Self, FE> {
+        let (encoding, bytes) = Self::read_raw(rd)?;
+        Ok(Self::from_raw(encoding, rd.codepage(), bytes))
+    }
 
     fn encode(&self, wd: &mut WriteStream) -> Result<(), FE> {
-        let (chars, _, _) = WINDOWS_1251.encode(self.str.as_str());
-        if self.encoding == FStringEncoding::ANSI {
-            wd.write_u32(chars.len() as u32 & !(1 << 31))?;
-            wd.write_bytes(&chars);
-        } else {
-            // WCS2
-            wd.write_u32(chars.len() as u32 | (1 << 31))?;
-            for &c in chars.iter() {
-                wd.write_u8(c)?;
-                wd.write_u8(0)?;
-            }
-        };
+        let bytes = self.as_bytes()?;
+        match self.encoding {
+            FStringEncoding::ANSI => wd.write_u32(bytes.len() as u32 & !(1 << 31))?,
+            FStringEncoding::WCS2 => wd.write_u32((bytes.len() / 2) as u32 | (1 << 31))?,
+        }
+        wd.write_bytes(&bytes);
         Ok(())
     }
 
+    // derived from current str, since str may be edited after decoding
     fn get_enc_size(&self) -> usize {
-        4 + match self.encoding {
-            FStringEncoding::ANSI => self.enc_len,
-            FStringEncoding::WCS2 => self.enc_len * 2,
-        }
+        4 + self.encode_bytes().0.len()
     }
 }
 
@@ -101,3 +187,95 @@ impl fmt::Display for FString {
         write!(f, "{}", self.str)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::tests::round_trip;
+    use super::*;
+    use encoding_rs::WINDOWS_1252;
+
+    fn ansi(bytes: &[u8]) -> Vec<u8> {
+        let mut enc = (bytes.len() as u32).to_le_bytes().to_vec();
+        enc.extend(bytes);
+        enc
+    }
+
+    fn wcs2(str: &str) -> Vec<u8> {
+        let units: Vec<u16> = str.encode_utf16().collect();
+        let mut enc = (units.len() as u32 | (1 << 31)).to_le_bytes().to_vec();
+        enc.extend(units.iter().flat_map(|unit| unit.to_le_bytes()));
+        enc
+    }
+
+    fn encode(fstr: &FString) -> Vec<u8> {
+        let mut wd = WriteStream::new(fstr.get_enc_size());
+        wd.write(fstr).unwrap();
+        wd.into_vec()
+    }
+
+    #[test]
+    fn ansi_round_trip() {
+        let fstr: FString = round_trip(&ansi(&[0xcf, 0xf0, 0xe8, 0xe2, 0xe5, 0xf2]));
+        assert_eq!(fstr.encoding(), FStringEncoding::ANSI);
+        assert_eq!(fstr.as_str(), "Привет");
+        assert!(round_trip::<FString>(&ansi(b"")).as_str().is_empty());
+    }
+
+    #[test]
+    fn wcs2_round_trip() {
+        for str in ["Hello", "Привет", "日本語", "a😀b"] {
+            let fstr: FString = round_trip(&wcs2(str));
+            assert_eq!(fstr.encoding(), FStringEncoding::WCS2);
+            assert_eq!(fstr.as_str(), str);
+        }
+    }
+
+    #[test]
+    fn unpaired_surrogate_kept() {
+        let mut enc = (2u32 | (1 << 31)).to_le_bytes().to_vec();
+        enc.extend([b'a', 0, 0x00, 0xd8]);
+        let fstr: FString = round_trip(&enc);
+        assert_eq!(fstr.as_str(), "a\u{fffd}");
+        assert_eq!(fstr.as_bytes().unwrap().as_ref(), &enc[4..]);
+    }
+
+    #[test]
+    fn edited_string_reencoded() {
+        let mut fstr: FString = round_trip(&ansi(b"Hello"));
+        fstr.set_str("Пока".to_string());
+        assert_eq!(encode(&fstr), ansi(&[0xcf, 0xee, 0xea, 0xe0]));
+        assert_eq!(fstr.get_enc_size(), 8);
+
+        fstr.set_encoding(FStringEncoding::WCS2, fstr.codepage());
+        assert_eq!(encode(&fstr), wcs2("Пока"));
+        assert_eq!(fstr.get_enc_size(), 12);
+    }
+
+    #[test]
+    fn unencodable_string_fails() {
+        let mut fstr: FString = round_trip(&ansi(b"Hello"));
+        fstr.set_str("日本".to_string());
+        assert!(matches!(fstr.as_bytes(), Err(FE::InvalidValue(_))));
+        let mut wd = WriteStream::new(0);
+        assert!(wd.write(&fstr).is_err());
+
+        fstr.set_encoding(FStringEncoding::WCS2, fstr.codepage());
+        assert_eq!(encode(&fstr), wcs2("日本"));
+    }
+
+    #[test]
+    fn codepage_decodes_ansi() {
+        let enc = ansi(&[0xe9]);
+        let mut rd = ReadStream::new(&enc, 0).with_codepage(WINDOWS_1252);
+        assert_eq!(rd.read::<FString>().unwrap().as_str(), "é");
+        assert_eq!(round_trip::<FString>(&enc).as_str(), "й");
+    }
+
+    #[test]
+    fn codepage_names() {
+        assert_eq!(codepage_for_name("1252"), Some(WINDOWS_1252));
+        assert_eq!(codepage_for_name("windows-1251"), Some(WINDOWS_1251));
+        assert_eq!(codepage_for_name("cp1251"), Some(WINDOWS_1251));
+        assert_eq!(codepage_for_name("nope"), None);
+    }
+}
@@ -0,0 +1,161 @@
+use super::entitylist::EntityList;
+use super::esh::ESHValue;
+use super::ferror::FError as FE;
+use super::refgraph::NO_ENTITY;
+
+// Items are ordinary entities. Character points to its inventory container
+// with INVENTORY_LINK, and container points to every item it holds with its
+// EntityFlags values. Only that container side is known and edited, links
+// from item back to its owner are left as they are. Item count and condition
+// are kept in properties whose names are not known yet, so they are not shown.
+// Container has fixed number of item links and giving needs an empty one
+pub const INVENTORY_LINK: &str = "Inventory";
+
+// links from character to entities it owns
+pub const OWNED_LINKS: [&str; 9] = [
+    INVENTORY_LINK,
+    "Hand1",
+    "Hand2",
+    "Armour",
+    "Hand1_",
+    "Hand2_",
+    "Armour_",
+    "unarmedSlot1",
+    "unarmedSlot2",
+];
+
+pub struct InventoryItem {
+    pub id: usize,
+    pub type_name: String,
+}
+
+fn find_container(entlist: &EntityList, owner: usize) -> Result<Option<usize>, FE> {
+    let esh = match entlist.get_entity(owner)?.esh()? {
+        Some(esh) => esh,
+        None => return Ok(None),
+    };
+    match esh.get(INVENTORY_LINK) {
+        Some(ESHValue::EntityFlags(eflags)) if eflags.entity_id as usize != NO_ENTITY => {
+            Ok(Some(eflags.entity_id as usize))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn get_container(entlist: &EntityList, owner: usize) -> Result<usize, FE> {
+    find_container(entlist, owner)?.ok_or(FE::NoInventory(owner))
+}
+
+// non-empty entities linked from owner's container
+pub fn get_items(entlist: &EntityList, owner: usize) -> Result<Vec<usize>, FE> {
+    let mut items: Vec<usize> = Vec::new();
+    for (_, target) in entlist
+        .get_entity(get_container(entlist, owner)?)?
+        .get_references()?
+    {
+        if target == NO_ENTITY || items.contains(&target) {
+            continue;
+        }
+        if !entlist.get_entity(target)?.is_empty() {
+            items.push(target);
+        }
+    }
+    Ok(items)
+}
+
+// entities owner links to with OWNED_LINKS, and items of its container
+pub fn get_owned(entlist: &EntityList, owner: usize) -> Result<Vec<usize>, FE> {
+    let mut owned: Vec<usize> = Vec::new();
+    let esh = match entlist.get_entity(owner)?.esh()? {
+        Some(esh) => esh,
+        None => return Ok(owned),
+    };
+
+    for name in OWNED_LINKS {
+        if let Some(ESHValue::EntityFlags(eflags)) = esh.get(name) {
+            let target = eflags.entity_id as usize;
+            if target != NO_ENTITY && !owned.contains(&target) {
+                owned.push(target);
+            }
+        }
+    }
+    if find_container(entlist, owner)?.is_some() {
+        for item in get_items(entlist, owner)? {
+            if !owned.contains(&item) {
+                owned.push(item);
+            }
+        }
+    }
+    Ok(owned)
+}
+
+pub fn holds(entlist: &EntityList, owner: usize, item: usize) -> Result<bool, FE> {
+    Ok(get_items(entlist, owner)?.contains(&item))
+}
+
+pub fn list_items(entlist: &EntityList, owner: usize) -> Result<Vec<InventoryItem>, FE> {
+    let mut items: Vec<InventoryItem> = Vec::new();
+    for id in get_items(entlist, owner)? {
+        let ent = entlist.get_entity(id)?;
+        items.push(InventoryItem {
+            id,
+            type_name: entlist.get_type_name(ent.type_idx).to_string(),
+        });
+    }
+    Ok(items)
+}
+
+// put item into first empty link of owner's container
+fn link(entlist: &mut EntityList, owner: usize, item: usize) -> Result<(), FE> {
+    let container = get_container(entlist, owner)?;
+    let esh = entlist.get_entity_mut(container)?.get_esh_mut()?;
+    for (name, value) in esh.props.iter_mut() {
+        if name == INVENTORY_LINK {
+            continue;
+        }
+        if let ESHValue::EntityFlags(eflags) = value {
+            if eflags.entity_id as usize == NO_ENTITY {
+                eflags.entity_id = item as u16;
+                return Ok(());
+            }
+        }
+    }
+    Err(FE::NoFreeItemLink(container))
+}
+
+// clear every link from owner's container to item
+fn unlink(entlist: &mut EntityList, owner: usize, item: usize) -> Result<(), FE> {
+    let container = get_container(entlist, owner)?;
+    entlist
+        .get_entity_mut(container)?
+        .remap_references(|target| if target == item { NO_ENTITY } else { target })
+}
+
+// clone item entity into owner's inventory
+pub fn give_item(entlist: &mut EntityList, owner: usize, item: usize) -> Result<usize, FE> {
+    let new_item = entlist.clone_entity(item)?;
+    link(entlist, owner, new_item)?;
+    Ok(new_item)
+}
+
+// move item out of owner's inventory into another one,
+// or remove item entity completely when there is no new owner
+pub fn take_item(
+    entlist: &mut EntityList,
+    owner: usize,
+    item: usize,
+    to: Option<usize>,
+) -> Result<(), FE> {
+    if !holds(entlist, owner, item)? {
+        return Err(FE::NotInInventory(item, owner));
+    }
+    if let Some(to) = to {
+        get_container(entlist, to)?;
+    }
+
+    unlink(entlist, owner, item)?;
+    match to {
+        Some(to) => link(entlist, to, item),
+        None => entlist.remove_entity(item),
+    }
+}
@@ -0,0 +1,211 @@
+use super::attributes::Attributes;
+use super::ferror::FError as FE;
+
+pub const MAX_LEVEL: u32 = 99;
+
+// Same experience table as Fallout 1/2 - level N requires
+// 1000 * (1 + 2 + ... + N-1) experience points
+pub fn xp_for_level(level: u32) -> u32 {
+    let level = level.clamp(1, MAX_LEVEL);
+    level * (level - 1) / 2 * 1000
+}
+
+pub fn level_for_xp(xp: u32) -> u32 {
+    let mut level = 1;
+    while level < MAX_LEVEL && xp >= xp_for_level(level + 1) {
+        level += 1;
+    }
+    level
+}
+
+fn checked_add(total: u32, value: u32, name: &str) -> Result<u32, FE> {
+    total
+        .checked_add(value)
+        .ok_or_else(|| FE::InvalidValue(format!("{} overflow", name)))
+}
+
+#[derive(Debug, Default)]
+pub struct LevelUp {
+    pub old_level: u32,
+    pub new_level: u32,
+    pub skill_points: u32,
+    pub perks: u32,
+    pub hit_points: u32,
+}
+
+impl Attributes {
+    pub fn get_level(&self) -> u32 {
+        level_for_xp(self.traits["experience"])
+    }
+
+    // derived skillPerLevel is stored already computed (5 + 2 * IN),
+    // so intelligence must not be added again
+    pub fn get_skill_points_per_level(&self) -> u32 {
+        self.derived["skillPerLevel"]
+    }
+
+    // 2 + EN/2 hit points per level, plus 4 for each rank of Lifegiver
+    pub fn get_hit_points_per_level(&self) -> u32 {
+        2 + self.stats["endurance"] / 2 + 4 * self.perks["lifegiver"]
+    }
+
+    pub fn add_experience(&mut self, xp: u32) -> Result<LevelUp, FE> {
+        let old_level = self.get_level();
+        self.traits["experience"] = checked_add(self.traits["experience"], xp, "experience")?;
+        let new_level = self.get_level();
+
+        let mut lvl = LevelUp {
+            old_level,
+            new_level,
+            ..Default::default()
+        };
+
+        let levels_per_perk = self.derived["levelsPerPerk"] as i32;
+        let mut lev_since_perk = self.get_lev_since_perk()?;
+        for _ in old_level..new_level {
+            lvl.skill_points = checked_add(
+                lvl.skill_points,
+                self.get_skill_points_per_level(),
+                "skillPoints",
+            )?;
+            lvl.hit_points =
+                checked_add(lvl.hit_points, self.get_hit_points_per_level(), "hitPoints")?;
+
+            lev_since_perk += 1;
+            if levels_per_perk > 0 && lev_since_perk >= levels_per_perk {
+                lvl.perks += 1;
+                lev_since_perk = 0;
+            }
+        }
+
+        self.traits["skillPoints"] =
+            checked_add(self.traits["skillPoints"], lvl.skill_points, "skillPoints")?;
+        self.traits["perksToTake"] =
+            checked_add(self.traits["perksToTake"], lvl.perks, "perksToTake")?;
+        self.derived["maxHitPoints"] =
+            checked_add(self.derived["maxHitPoints"], lvl.hit_points, "maxHitPoints")?;
+        self.set_lev_since_perk(lev_since_perk);
+
+        Ok(lvl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prop(name: &str, data_type: u32, data: &[u8]) -> Vec<u8> {
+        let mut prop = (name.len() as u32).to_le_bytes().to_vec();
+        prop.extend(name.as_bytes());
+        prop.extend(data_type.to_le_bytes());
+        prop.extend((data.len() as u32).to_le_bytes());
+        prop.extend(data);
+        prop
+    }
+
+    // zeroed attributes with levelsPerPerk, skillPerLevel and endurance set
+    fn attributes(levels_per_perk: u32, lev_since_perk: i32) -> Attributes {
+        let mut esbin = 0u32.to_le_bytes().to_vec();
+        esbin.extend(b"<attribs>\x001\0");
+        esbin.extend(vec![0u8; (7 + 11 + 26 + 18) * 4 + 18 + 38 + (111 + 10) * 4]);
+
+        let mut esh = b"<esh>\x002\0".to_vec();
+        esh.extend(3u32.to_le_bytes());
+        esh.extend(prop("levSincePerk", 3, &lev_since_perk.to_le_bytes()));
+        esh.extend(prop("Binary", 1, &[1]));
+        esh.extend(prop("esbin", 11, &esbin));
+
+        let mut bin = (esh.len() as u32).to_le_bytes().to_vec();
+        bin.extend(esh);
+        let mut attrs = Attributes::from_binary(&bin).unwrap();
+        attrs.derived["levelsPerPerk"] = levels_per_perk;
+        attrs.derived["skillPerLevel"] = 15;
+        attrs.stats["endurance"] = 6;
+        attrs
+    }
+
+    #[test]
+    fn xp_table() {
+        assert_eq!(xp_for_level(0), 0);
+        assert_eq!(xp_for_level(1), 0);
+        assert_eq!(xp_for_level(2), 1000);
+        assert_eq!(xp_for_level(3), 3000);
+        assert_eq!(xp_for_level(4), 6000);
+        assert_eq!(xp_for_level(MAX_LEVEL), 4_851_000);
+        assert_eq!(xp_for_level(MAX_LEVEL + 1), xp_for_level(MAX_LEVEL));
+    }
+
+    #[test]
+    fn level_boundaries() {
+        assert_eq!(level_for_xp(0), 1);
+        assert_eq!(level_for_xp(999), 1);
+        assert_eq!(level_for_xp(1000), 2);
+        assert_eq!(level_for_xp(2999), 2);
+        assert_eq!(level_for_xp(3000), 3);
+        assert_eq!(level_for_xp(4_850_999), MAX_LEVEL - 1);
+        assert_eq!(level_for_xp(4_851_000), MAX_LEVEL);
+        assert_eq!(level_for_xp(u32::MAX), MAX_LEVEL);
+        for level in 1..=MAX_LEVEL {
+            assert_eq!(level_for_xp(xp_for_level(level)), level);
+        }
+    }
+
+    #[test]
+    fn several_levels_with_perk_carry_over() {
+        let mut attrs = attributes(3, 2);
+        attrs.perks["lifegiver"] = 1;
+
+        // 1 -> 4, perk on first level up, then 2 levels toward next one
+        let lvl = attrs.add_experience(6000).unwrap();
+        assert_eq!((lvl.old_level, lvl.new_level), (1, 4));
+        assert_eq!(lvl.skill_points, 3 * 15);
+        assert_eq!(lvl.hit_points, 3 * (2 + 3 + 4));
+        assert_eq!(lvl.perks, 1);
+        assert_eq!(attrs.get_lev_since_perk().unwrap(), 2);
+        assert_eq!(attrs.traits["skillPoints"], 45);
+        assert_eq!(attrs.traits["perksToTake"], 1);
+        assert_eq!(attrs.derived["maxHitPoints"], 27);
+
+        // 4 -> 5 completes carried over perk
+        let lvl = attrs.add_experience(4000).unwrap();
+        assert_eq!((lvl.old_level, lvl.new_level), (4, 5));
+        assert_eq!(lvl.perks, 1);
+        assert_eq!(attrs.get_lev_since_perk().unwrap(), 0);
+        assert_eq!(attrs.traits["experience"], 10000);
+        assert_eq!(attrs.traits["skillPoints"], 60);
+        assert_eq!(attrs.traits["perksToTake"], 2);
+        assert_eq!(attrs.derived["maxHitPoints"], 36);
+    }
+
+    #[test]
+    fn xp_within_level() {
+        let mut attrs = attributes(3, 0);
+        let lvl = attrs.add_experience(999).unwrap();
+        assert_eq!((lvl.old_level, lvl.new_level), (1, 1));
+        assert_eq!((lvl.skill_points, lvl.perks, lvl.hit_points), (0, 0, 0));
+        assert_eq!(attrs.get_lev_since_perk().unwrap(), 0);
+    }
+
+    #[test]
+    fn no_perks_without_levels_per_perk() {
+        let mut attrs = attributes(0, 0);
+        let lvl = attrs.add_experience(10000).unwrap();
+        assert_eq!(lvl.new_level, 5);
+        assert_eq!(lvl.perks, 0);
+        assert_eq!(attrs.get_lev_since_perk().unwrap(), 4);
+    }
+
+    #[test]
+    fn overflow_is_error() {
+        let mut attrs = attributes(3, 0);
+        attrs.traits["experience"] = u32::MAX - 10;
+        assert!(matches!(attrs.add_experience(11), Err(FE::InvalidValue(_))));
+
+        let mut attrs = attributes(3, 0);
+        attrs.traits["skillPoints"] = u32::MAX;
+        assert!(matches!(
+            attrs.add_experience(1000),
+            Err(FE::InvalidValue(_))
+        ));
+    }
+}
@@ -21,7 +21,10 @@ impl Raw {
     }
 
     pub fn find_str_backwards(&self, str: &str) -> Option<usize> {
-        for i in (0..self.mem.len() - str.len()).step_by(1024).rev() {
+        for i in (0..self.mem.len().saturating_sub(str.len()))
+            .step_by(1024)
+            .rev()
+        {
             match self.find_str(str, i) {
                 Some(offset) => return Some(i + offset),
                 None => continue,
@@ -51,25 +54,25 @@ impl Raw {
         );
 
         let mut sorted = blocks;
-        sorted.sort_by(|a, b| a.offset.cmp(&b.offset));
+        sorted.sort_by_key(|b| b.offset);
 
         let file_end = self.size;
         let mut prev_end: usize = 0;
         for block in sorted.iter() {
             // prev
-            file.write(&self.mem[prev_end..block.offset])?;
+            file.write_all(&self.mem[prev_end..block.offset])?;
             // data
-            file.write(&block.mem)?;
+            file.write_all(&block.mem)?;
             // padding
             if block.size > block.mem.len() {
                 for _ in 0..block.size - block.mem.len() {
-                    file.write(&[0])?;
+                    file.write_all(&[0])?;
                 }
             }
             prev_end = block.offset + block.size;
         }
         if prev_end < file_end {
-            file.write(&self.mem[prev_end..file_end])?;
+            file.write_all(&self.mem[prev_end..file_end])?;
         }
 
         file.flush()?;
@@ -0,0 +1,95 @@
+use super::entitylist::EntityList;
+use super::ferror::FError as FE;
+use std::collections::BTreeSet;
+use std::io::Write;
+
+// entity_id of 0 is used as "no entity"
+pub const NO_ENTITY: usize = 0;
+
+#[derive(Debug)]
+pub struct Reference {
+    pub from: usize,
+    pub name: String,
+    pub to: usize,
+}
+
+// Graph of EntityFlags links between entities of EntityList
+pub struct RefGraph {
+    pub refs: Vec<Reference>,
+}
+
+impl RefGraph {
+    pub fn build(entlist: &EntityList) -> Result<Self, FE> {
+        let mut refs: Vec<Reference> = Vec::new();
+        for (from, ent) in entlist {
+            for (name, to) in ent.get_references()? {
+                if to != NO_ENTITY {
+                    refs.push(Reference {
+                        from,
+                        name: name.to_string(),
+                        to,
+                    });
+                }
+            }
+        }
+
+        Ok(RefGraph { refs })
+    }
+
+    pub fn references_from(&self, id: usize) -> impl Iterator<Item = &Reference> {
+        self.refs.iter().filter(move |r| r.from == id)
+    }
+
+    pub fn references_to(&self, id: usize) -> impl Iterator<Item = &Reference> {
+        self.refs.iter().filter(move |r| r.to == id)
+    }
+
+    // references past the end of list or at empty NO_ESH slots
+    pub fn dangling<'a>(&'a self, entlist: &'a EntityList) -> impl Iterator<Item = &'a Reference> {
+        self.refs.iter().filter(|r| !entlist.is_valid_id(r.to))
+    }
+
+    pub fn write_dot(&self, entlist: &EntityList, w: &mut impl Write) -> Result<(), FE> {
+        let mut nodes: BTreeSet<usize> = BTreeSet::new();
+        for r in self.refs.iter() {
+            nodes.insert(r.from);
+            nodes.insert(r.to);
+        }
+
+        writeln!(w, "digraph entities {{")?;
+        for id in nodes {
+            if entlist.is_valid_id(id) {
+                let ent = entlist.get_entity(id)?;
+                let type_name = entlist.get_type_name(ent.type_idx);
+                writeln!(
+                    w,
+                    "\tn{} [label=\"{} {}\"];",
+                    id,
+                    id,
+                    dot_escape(type_name.as_str())
+                )?;
+            } else {
+                writeln!(
+                    w,
+                    "\tn{} [label=\"{} <missing>\", style=dashed, color=red];",
+                    id, id
+                )?;
+            }
+        }
+        for r in self.refs.iter() {
+            writeln!(
+                w,
+                "\tn{} -> n{} [label=\"{}\"];",
+                r.from,
+                r.to,
+                dot_escape(&r.name)
+            )?;
+        }
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+}
+
+fn dot_escape(str: &str) -> String {
+    str.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -1,22 +1,39 @@
 use super::decoder::DecoderCtx;
 use super::ferror::FError as FE;
+use super::fstring::{CodePage, FString};
 use super::raw::Raw;
 use super::stream::{ReadStream, WriteStream};
+use super::tag::Tag;
 use super::world::World;
 use byteorder::{ByteOrder, LittleEndian};
+use fot_derive::Decoder;
 use std::path::Path;
 use std::str;
 
+// Save header is only read for information, it's kept
+// in raw and written back unchanged
+#[derive(Debug, Decoder)]
+pub struct SaveHeader {
+    pub tag: Tag,
+    unk1: u8,
+    unk2: FString,
+    pub name: FString,
+    pub location: FString,
+    pub date: FString,
+}
+
 pub struct Save {
     pub raw: Raw,
+    pub header: Option<SaveHeader>,
     pub world: World,
 }
 
 impl Save {
+    const SAVEH_TAG: &str = "<saveh>";
     const WORLD_TAG: &str = "<world>";
     const CAMPAIGN_TAG: &str = "<campaign>";
 
-    pub fn load(path: &Path) -> Result<Self, FE> {
+    pub fn load(path: &Path, codepage: CodePage, lazy: bool) -> Result<Self, FE> {
         let raw = Raw::load_file(path)?;
         let world_offset = match raw.find_str_backwards(Self::WORLD_TAG) {
             Some(offset) => offset,
@@ -30,7 +47,8 @@ impl Save {
                 None => return Err(FE::NoCampaign),
             };
 
-            for i in (campaign - 256..campaign).rev() {
+            // world size is right before campaign, but not before world itself
+            for i in (campaign.saturating_sub(256).max(world_offset)..campaign).rev() {
                 let fsize = LittleEndian::read_u32(&raw.mem[i..i + 4]);
                 if fsize & (1 << 31) != 0 {
                     let size = fsize ^ (1 << 31);
@@ -45,9 +63,18 @@ impl Save {
             return Err(FE::UnknownWorldSize);
         }
 
-        let mut rd = ReadStream::new(&raw.mem, world_offset);
-        let world = World::decode(&mut rd, (world_offset, world_size))?;
-        Ok(Save { raw, world })
+        let mut rd = ReadStream::new(&raw.mem, world_offset)
+            .with_codepage(codepage)
+            .with_lazy(lazy);
+        let world = World::decode(&mut rd, (world_offset, world_size))
+            .map_err(|fe| fe.context("World", rd.offset()))?;
+
+        // header is optional, unknown layout shouldn't prevent editing world
+        let header = raw.find_str(Self::SAVEH_TAG, 0).and_then(|offset| {
+            let mut rd = ReadStream::new(&raw.mem, offset).with_codepage(codepage);
+            rd.read::<SaveHeader>().ok()
+        });
+        Ok(Save { raw, header, world })
     }
 
     pub fn save(&self, path: &Path) -> Result<(), FE> {
@@ -61,3 +88,50 @@ impl Save {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::tests::round_trip;
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn header_derive_round_trip() {
+        let mut bytes: Vec<u8> = b"<saveh>\x001\0\x07".to_vec();
+        for str in ["x", "Save Name", "Bunker", "2026-10-19"] {
+            bytes.extend((str.len() as u32).to_le_bytes());
+            bytes.extend(str.as_bytes());
+        }
+
+        let header: SaveHeader = round_trip(&bytes);
+        assert_eq!(header.tag.name, "<saveh>");
+        assert_eq!(header.unk1, 7);
+        assert_eq!(header.name, *"Save Name");
+        assert_eq!(header.location, *"Bunker");
+        assert_eq!(header.date, *"2026-10-19");
+    }
+
+    fn load_bytes(name: &str, bytes: &[u8]) -> Result<Save, FE> {
+        let path = std::env::temp_dir().join(format!("fot-save-{}-{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        let save = Save::load(&path, encoding_rs::WINDOWS_1251, false);
+        fs::remove_file(&path).unwrap();
+        save
+    }
+
+    #[test]
+    fn load_short_files() {
+        assert!(matches!(load_bytes("empty", b""), Err(FE::NoWorld)));
+        assert!(matches!(load_bytes("tag", b"<world>"), Err(FE::NoWorld)));
+        assert!(matches!(
+            load_bytes("nocampaign", b"<world>\0"),
+            Err(FE::NoCampaign)
+        ));
+        // campaign closer than 256 bytes to start of file, and size-like
+        // value before world
+        assert!(matches!(
+            load_bytes("campaign", b"\x01\0\0\x80<world>\0<campaign>"),
+            Err(FE::UnknownWorldSize)
+        ));
+    }
+}
@@ -3,12 +3,38 @@ use super::ferror::FError as FE;
 use super::fstring::FString;
 use super::stream::{ReadStream, WriteStream};
 use super::tag::Tag;
+use fot_derive::Decoder;
 use indexmap::IndexMap;
 
+// SGD header is still opaque: 0x48 bytes after tag, read as 18 little
+// endian u32 values only to keep them. None of them is identified, so fields
+// are named by their offset after tag and written back unchanged
+#[derive(Debug, Clone, Decoder)]
+pub struct SGDHeader {
+    pub unk_00: u32,
+    pub unk_04: u32,
+    pub unk_08: u32,
+    pub unk_0c: u32,
+    pub unk_10: u32,
+    pub unk_14: u32,
+    pub unk_18: u32,
+    pub unk_1c: u32,
+    pub unk_20: u32,
+    pub unk_24: u32,
+    pub unk_28: u32,
+    pub unk_2c: u32,
+    pub unk_30: u32,
+    pub unk_34: u32,
+    pub unk_38: u32,
+    pub unk_3c: u32,
+    pub unk_40: u32,
+    pub unk_44: u32,
+}
+
 #[derive(Debug)]
 pub struct SGD {
-    tag: Tag,
-    unk1: Vec<u8>,
+    pub tag: Tag,
+    pub header: SGDHeader,
     pub dialogs: IndexMap<FString, Vec<FString>>,
     enc_size: usize,
 }
@@ -17,22 +43,30 @@ impl Decoder for SGD {
     fn decode<'a>(rd: &mut ReadStream<'a>) -> Result<Self, FE> {
         let offset = rd.offset();
         let tag: Tag = rd.read()?;
-        let unk1 = rd.read_bytes(0x48)?;
+        let header: SGDHeader = rd.read()?;
         let mut dialogs: IndexMap<FString, Vec<FString>> = IndexMap::new();
 
         let n = rd.read_u32()? as usize;
         let mut names: Vec<FString> = Vec::with_capacity(n);
-        for _ in 0..n {
-            names.push(rd.read::<FString>()?);
+        for i in 0..n {
+            let name: FString = rd
+                .read()
+                .map_err(|fe| fe.context(format!("speaker {}", i), rd.offset()))?;
+            names.push(name);
         }
 
         let m = rd.read_u32()? as usize;
-        assert!(m == n, "SGD m != n");
-        for _ in 0..m {
+        if m != n {
+            return Err(FE::SGDCountMismatch(n, m));
+        }
+        for i in 0..m {
             let k = rd.read_u32()? as usize;
             let mut lines: Vec<FString> = Vec::with_capacity(k);
-            for _ in 0..k {
-                lines.push(rd.read::<FString>()?);
+            for j in 0..k {
+                let line: FString = rd
+                    .read()
+                    .map_err(|fe| fe.context(format!("dialog {} line {}", i, j), rd.offset()))?;
+                lines.push(line);
             }
 
             dialogs.insert(names.remove(0), lines);
@@ -41,7 +75,7 @@ impl Decoder for SGD {
         let enc_size = rd.offset() - offset;
         Ok(SGD {
             tag,
-            unk1,
+            header,
             dialogs,
             enc_size,
         })
@@ -49,7 +83,7 @@ impl Decoder for SGD {
 
     fn encode(&self, wd: &mut WriteStream) -> Result<(), FE> {
         wd.write(&self.tag)?;
-        wd.write_bytes(&self.unk1);
+        wd.write(&self.header)?;
 
         wd.write_u32(self.dialogs.len() as u32)?;
         for name in self.dialogs.keys() {
@@ -71,3 +105,55 @@ impl Decoder for SGD {
         self.enc_size
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::tests::round_trip;
+    use super::*;
+
+    fn fstring(bytes: &mut Vec<u8>, str: &str) {
+        bytes.extend((str.len() as u32).to_le_bytes());
+        bytes.extend(str.as_bytes());
+    }
+
+    // SGD with speakers, and dialog list count that may differ from them
+    fn sgd(speakers: &[(&str, &[&str])], lists: usize) -> Vec<u8> {
+        let mut bytes: Vec<u8> = b"<sgd>\x001\0".to_vec();
+        bytes.extend((0..18u32).flat_map(|i| i.to_le_bytes()));
+        bytes.extend((speakers.len() as u32).to_le_bytes());
+        for (name, _) in speakers {
+            fstring(&mut bytes, name);
+        }
+        bytes.extend((lists as u32).to_le_bytes());
+        for (_, lines) in speakers.iter().take(lists) {
+            bytes.extend((lines.len() as u32).to_le_bytes());
+            for line in lines.iter() {
+                fstring(&mut bytes, line);
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn dialogs_round_trip() {
+        let sgd: SGD = round_trip(&sgd(&[("Jo", &["Hello", "Bye"]), ("Vault:13", &[])], 2));
+        assert_eq!(sgd.header.unk_00, 0);
+        assert_eq!(sgd.header.unk_44, 17);
+        let dialogs: Vec<(String, usize)> = sgd
+            .dialogs
+            .iter()
+            .map(|(name, lines)| (name.to_string(), lines.len()))
+            .collect();
+        assert_eq!(
+            dialogs,
+            vec![("Jo".to_string(), 2), ("Vault:13".to_string(), 0)]
+        );
+    }
+
+    #[test]
+    fn count_mismatch() {
+        let bytes = sgd(&[("Jo", &["Hello"]), ("Vault:13", &[])], 1);
+        let mut rd = ReadStream::new(&bytes, 0);
+        assert!(matches!(rd.read::<SGD>(), Err(FE::SGDCountMismatch(2, 1))));
+    }
+}
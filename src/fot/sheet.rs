@@ -0,0 +1,230 @@
+use super::entity::Entity;
+use super::entitylist::EntityList;
+use super::esh::ESHValue;
+use super::ferror::FError as FE;
+use std::io::Write;
+
+const CURRENT_ATTRIBUTES: [&str; 3] = ["hitPoints", "poisonPoints", "radiationPoints"];
+
+// Character sheet - everything worth knowing about character
+// collected from Attributes, Modifiers and "Current Attributes"
+pub struct CharacterSheet {
+    pub id: usize,
+    pub name: String,
+    pub type_name: String,
+    pub level: u32,
+    pub stats: Vec<(&'static str, i32)>,
+    pub skills: Vec<(&'static str, i32, bool)>,
+    pub traits: Vec<(&'static str, u32)>,
+    pub opt_traits: Vec<&'static str>,
+    pub perks: Vec<(&'static str, u32)>,
+    pub addictions: Vec<(&'static str, u32)>,
+    pub current: Vec<(&'static str, i32)>,
+}
+
+impl CharacterSheet {
+    pub fn from_entity(entlist: &EntityList, id: usize, ent: &Entity) -> Result<Self, FE> {
+        let esh = ent.get_esh()?;
+        let attrs = ent.get_attributes()?;
+        let mods = ent.get_modifiers().ok();
+
+        let name = match esh.get("Display Name") {
+            Some(ESHValue::String(name)) => name.as_str().to_string(),
+            _ => String::new(),
+        };
+        let type_name = entlist.get_type_name(ent.type_idx).to_string();
+
+        let current = match esh.get_nested("Current Attributes") {
+            Ok(current) => CURRENT_ATTRIBUTES
+                .iter()
+                .filter_map(|&name| match current.get(name) {
+                    Some(ESHValue::Int(val)) => Some((name, *val)),
+                    _ => None,
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        Ok(CharacterSheet {
+            id,
+            name,
+            type_name,
+            level: attrs.get_level(),
+            stats: attrs
+                .get_effective_stats(mods.as_ref())
+                .into_iter()
+                .collect(),
+            skills: attrs
+                .get_effective_skills(mods.as_ref())
+                .into_iter()
+                .map(|(name, value)| (name, value, attrs.skill_tags[name]))
+                .collect(),
+            traits: attrs.traits.iter().map(|(&k, &v)| (k, v)).collect(),
+            opt_traits: attrs
+                .opt_traits
+                .iter()
+                .filter(|(_, &v)| v)
+                .map(|(&k, _)| k)
+                .collect(),
+            perks: attrs
+                .perks
+                .iter()
+                .filter(|(_, &v)| v != 0)
+                .map(|(&k, &v)| (k, v))
+                .collect(),
+            addictions: attrs
+                .addictions
+                .iter()
+                .filter(|(_, &v)| v != 0)
+                .map(|(&k, &v)| (k, v))
+                .collect(),
+            current,
+        })
+    }
+
+    fn tag_marker(tagged: bool) -> &'static str {
+        if tagged {
+            "*"
+        } else {
+            ""
+        }
+    }
+
+    pub fn write_text(&self, w: &mut impl Write) -> Result<(), FE> {
+        writeln!(w, "{}\t{}\t{}", self.id, self.name, self.type_name)?;
+        writeln!(w, "level\t{}", self.level)?;
+        for (name, value) in &self.current {
+            writeln!(w, "{}\t{}", name, value)?;
+        }
+        writeln!(w, "stats")?;
+        for (name, value) in &self.stats {
+            writeln!(w, "\t{}\t{}", name, value)?;
+        }
+        writeln!(w, "skills")?;
+        for (name, value, tagged) in &self.skills {
+            writeln!(w, "\t{}{}\t{}", name, Self::tag_marker(*tagged), value)?;
+        }
+        writeln!(w, "traits")?;
+        for (name, value) in &self.traits {
+            writeln!(w, "\t{}\t{}", name, value)?;
+        }
+        for name in &self.opt_traits {
+            writeln!(w, "\t{}", name)?;
+        }
+        writeln!(w, "perks")?;
+        for (name, value) in &self.perks {
+            writeln!(w, "\t{}\t{}", name, value)?;
+        }
+        writeln!(w, "addictions")?;
+        for (name, value) in &self.addictions {
+            writeln!(w, "\t{}\t{}", name, value)?;
+        }
+        writeln!(w)?;
+        Ok(())
+    }
+
+    pub fn write_markdown(&self, w: &mut impl Write) -> Result<(), FE> {
+        writeln!(w, "## {} ({}, id {})", self.name, self.type_name, self.id)?;
+        writeln!(w)?;
+        write!(w, "**Level** {}", self.level)?;
+        for (name, value) in &self.current {
+            write!(w, " | **{}** {}", name, value)?;
+        }
+        writeln!(w)?;
+
+        writeln!(w, "\n### Stats\n\n| Stat | Value |\n|---|---|")?;
+        for (name, value) in &self.stats {
+            writeln!(w, "| {} | {} |", name, value)?;
+        }
+        writeln!(w, "\n### Skills\n\n| Skill | Value |\n|---|---|")?;
+        for (name, value, tagged) in &self.skills {
+            writeln!(w, "| {}{} | {} |", name, Self::tag_marker(*tagged), value)?;
+        }
+        writeln!(w, "\n### Traits\n\n| Trait | Value |\n|---|---|")?;
+        for (name, value) in &self.traits {
+            writeln!(w, "| {} | {} |", name, value)?;
+        }
+        for name in &self.opt_traits {
+            writeln!(w, "| {} | |", name)?;
+        }
+        writeln!(w, "\n### Perks\n")?;
+        for (name, value) in &self.perks {
+            writeln!(w, "- {} ({})", name, value)?;
+        }
+        writeln!(w, "\n### Addictions\n")?;
+        for (name, value) in &self.addictions {
+            writeln!(w, "- {} ({})", name, value)?;
+        }
+        writeln!(w)?;
+        Ok(())
+    }
+
+    pub fn write_json(&self, w: &mut impl Write) -> Result<(), FE> {
+        fn pairs<V: std::fmt::Display>(items: impl Iterator<Item = (&'static str, V)>) -> String {
+            items
+                .map(|(k, v)| format!("\"{}\":{}", k, v))
+                .collect::<Vec<String>>()
+                .join(",")
+        }
+
+        write!(
+            w,
+            "{{\"id\":{},\"name\":{},\"type\":{},\"level\":{}",
+            self.id,
+            json_string(&self.name),
+            json_string(&self.type_name),
+            self.level
+        )?;
+        write!(
+            w,
+            ",\"current\":{{{}}}",
+            pairs(self.current.iter().copied())
+        )?;
+        write!(w, ",\"stats\":{{{}}}", pairs(self.stats.iter().copied()))?;
+        write!(
+            w,
+            ",\"skills\":{{{}}}",
+            self.skills
+                .iter()
+                .map(|(k, v, t)| format!("\"{}\":{{\"value\":{},\"tagged\":{}}}", k, v, t))
+                .collect::<Vec<String>>()
+                .join(",")
+        )?;
+        write!(w, ",\"traits\":{{{}}}", pairs(self.traits.iter().copied()))?;
+        write!(
+            w,
+            ",\"opt_traits\":[{}]",
+            self.opt_traits
+                .iter()
+                .map(|k| format!("\"{}\"", k))
+                .collect::<Vec<String>>()
+                .join(",")
+        )?;
+        write!(w, ",\"perks\":{{{}}}", pairs(self.perks.iter().copied()))?;
+        write!(
+            w,
+            ",\"addictions\":{{{}}}",
+            pairs(self.addictions.iter().copied())
+        )?;
+        write!(w, "}}")?;
+        Ok(())
+    }
+}
+
+pub fn json_string(str: &str) -> String {
+    let mut out = String::with_capacity(str.len() + 2);
+    out.push('"');
+    for c in str.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -2,27 +2,115 @@ use super::decoder::Decoder;
 use super::ferror::FError as FE;
 use super::stream::{ReadStream, WriteStream};
 use super::tag::Tag;
+use byteorder::{ByteOrder, LittleEndian};
+use std::io::Write;
+
+// Layout of 0x14 bytes after tag is not known yet (likely game time or
+// squad id), so they are kept raw with offset in uncompressed world data
+pub const SSG_DATA_SIZE: usize = 0x14;
 
 #[derive(Debug)]
 pub struct SSG {
-    tag: Tag,
-    unk1: Vec<u8>,
+    pub tag: Tag,
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+impl SSG {
+    pub fn get_u32(&self, pos: usize) -> Result<u32, FE> {
+        match pos.checked_add(4).and_then(|end| self.data.get(pos..end)) {
+            Some(bytes) => Ok(LittleEndian::read_u32(bytes)),
+            None => Err(FE::StreamOverflow(pos, self.data.len(), 4)),
+        }
+    }
+
+    pub fn set_u32(&mut self, pos: usize, value: u32) -> Result<(), FE> {
+        match pos
+            .checked_add(4)
+            .and_then(|end| self.data.get_mut(pos..end))
+        {
+            Some(bytes) => {
+                LittleEndian::write_u32(bytes, value);
+                Ok(())
+            }
+            None => Err(FE::StreamOverflow(pos, self.data.len(), 4)),
+        }
+    }
+
+    // hex dump, 16 bytes per row prefixed with offset in world data
+    pub fn write_hex(&self, w: &mut impl Write) -> Result<(), FE> {
+        for (i, row) in self.data.chunks(16).enumerate() {
+            write!(w, "{:08x} ", self.offset + i * 16)?;
+            for byte in row {
+                write!(w, " {:02x}", byte)?;
+            }
+            write!(w, "{}  ", "   ".repeat(16 - row.len()))?;
+            for &byte in row {
+                let c = if byte.is_ascii_graphic() {
+                    byte as char
+                } else {
+                    '.'
+                };
+                write!(w, "{}", c)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
 }
 
 impl Decoder for SSG {
     fn decode<'a>(rd: &mut ReadStream<'a>) -> Result<Self, FE> {
         let tag: Tag = rd.read()?;
-        let unk1 = rd.read_bytes(0x14)?;
-        Ok(SSG { tag, unk1 })
+        let offset = rd.offset();
+        let data = rd.read_bytes(SSG_DATA_SIZE)?;
+        Ok(SSG { tag, offset, data })
     }
 
     fn encode(&self, wd: &mut WriteStream) -> Result<(), FE> {
         wd.write(&self.tag)?;
-        wd.write_bytes(&self.unk1);
+        wd.write_bytes(&self.data);
         Ok(())
     }
 
     fn get_enc_size(&self) -> usize {
-        self.tag.get_enc_size() + 0x14
+        self.tag.get_enc_size() + SSG_DATA_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssg() -> SSG {
+        let mut mem: Vec<u8> = b"<ssg>\x001\0".to_vec();
+        mem.extend(0..SSG_DATA_SIZE as u8);
+        ReadStream::new(&mem, 0).read().unwrap()
+    }
+
+    #[test]
+    fn get_u32_bounds() {
+        let ssg = ssg();
+        assert_eq!(ssg.get_u32(0).unwrap(), 0x03020100);
+        assert_eq!(ssg.get_u32(0x10).unwrap(), 0x13121110);
+        assert!(matches!(
+            ssg.get_u32(0x11),
+            Err(FE::StreamOverflow(0x11, 0x14, 4))
+        ));
+        assert!(ssg.get_u32(SSG_DATA_SIZE).is_err());
+        assert!(ssg.get_u32(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn set_u32_bounds() {
+        let mut ssg = ssg();
+        ssg.set_u32(0x10, 0xdeadbeef).unwrap();
+        assert_eq!(ssg.get_u32(0x10).unwrap(), 0xdeadbeef);
+        assert_eq!(&ssg.data[0x10..], &[0xef, 0xbe, 0xad, 0xde]);
+
+        let data = ssg.data.clone();
+        assert!(ssg.set_u32(0x11, 1).is_err());
+        assert!(ssg.set_u32(usize::MAX, 1).is_err());
+        assert_eq!(ssg.data, data);
     }
 }
@@ -1,19 +1,60 @@
 use super::decoder::{Decoder, DecoderCtx};
 use super::ferror::FError as FE;
+use super::fstring::{CodePage, DEFAULT_CODEPAGE};
 use super::raw::Raw;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::Cursor;
+use std::rc::Rc;
 
 pub struct ReadStream<'a> {
     mem: &'a [u8],
     rdr: Cursor<&'a [u8]>,
+    codepage: CodePage,
+    lazy: bool,
+    shared: Option<Rc<[u8]>>,
 }
 
 impl<'a> ReadStream<'a> {
     pub fn new(mem: &'a [u8], offset: usize) -> ReadStream<'a> {
         let mut rdr = Cursor::new(mem);
         rdr.set_position(offset as u64);
-        ReadStream { mem, rdr }
+        ReadStream {
+            mem,
+            rdr,
+            codepage: DEFAULT_CODEPAGE,
+            lazy: false,
+            shared: None,
+        }
+    }
+
+    // code page for decoding ANSI strings
+    pub fn with_codepage(mut self, codepage: CodePage) -> Self {
+        self.codepage = codepage;
+        self
+    }
+
+    pub fn codepage(&self) -> CodePage {
+        self.codepage
+    }
+
+    // lazy decoding keeps ESH of world entities undecoded until accessed
+    pub fn with_lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+
+    // same bytes as mem, but owned, so decoded structures can keep slices of it
+    pub fn with_shared(mut self, shared: Rc<[u8]>) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    pub fn shared(&self) -> Option<Rc<[u8]>> {
+        self.shared.clone()
     }
 
     pub fn offset(&self) -> usize {
@@ -38,7 +79,6 @@ impl<'a> ReadStream<'a> {
 
     pub fn as_bytes(&mut self, size: usize) -> Result<&'a [u8], FE> {
         if self.offset() + size > self.mem.len() {
-            dbg!(self.offset(), size, self.mem.len());
             Err(FE::StreamOverflow(self.offset(), self.size(), size))
         } else {
             let buf = &self.mem[self.offset()..self.offset() + size];
@@ -58,11 +98,11 @@ impl<'a> ReadStream<'a> {
     // read_opt - decode with optional paramters. required for complex structure
     // with different origins (save / entfile) like entities
     pub fn read_ctx<T: DecoderCtx<DCtx, ECtx>, DCtx, ECtx>(&mut self, ctx: DCtx) -> Result<T, FE> {
-        Ok(T::decode(self, ctx)?)
+        T::decode(self, ctx)
     }
 
     pub fn read<T: Decoder>(&mut self) -> Result<T, FE> {
-        Ok(T::decode(self)?)
+        T::decode(self)
     }
 
     pub fn read_u8(&mut self) -> Result<u8, FE> {
@@ -108,7 +148,7 @@ impl WriteStream {
     pub fn into_raw(self, offset: usize, size: usize) -> Raw {
         let buf_size = self.buf.get_ref().len();
         Raw {
-            offset: offset,
+            offset,
             size: if size == 0 { buf_size } else { size },
             mem: self.into_vec(),
         }
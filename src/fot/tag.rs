@@ -1,31 +1,11 @@
-use super::decoder::Decoder;
-use super::ferror::FError as FE;
-use super::stream::{ReadStream, WriteStream};
+use fot_derive::Decoder;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Decoder)]
 pub struct Tag {
     pub name: String,
     pub version: String,
 }
 
-impl Decoder for Tag {
-    fn decode<'a>(rd: &mut ReadStream<'a>) -> Result<Self, FE> {
-        let name: String = rd.read()?;
-        let version: String = rd.read()?;
-        Ok(Tag { name, version })
-    }
-
-    fn encode(&self, wd: &mut WriteStream) -> Result<(), FE> {
-        wd.write(&self.name)?;
-        wd.write(&self.version)?;
-        Ok(())
-    }
-
-    fn get_enc_size(&self) -> usize {
-        self.name.get_enc_size() + self.version.get_enc_size()
-    }
-}
-
 // struct for Tag consts
 pub struct CTag<'a> {
     pub name: &'a str,
@@ -40,3 +20,16 @@ impl<'a> CTag<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::decoder::tests::round_trip;
+    use super::*;
+
+    #[test]
+    fn derive_round_trip() {
+        let tag: Tag = round_trip(b"<world>\x009\0");
+        assert_eq!(tag.name, "<world>");
+        assert_eq!(tag.version, "9");
+    }
+}
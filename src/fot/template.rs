@@ -0,0 +1,210 @@
+use super::attributes::{Attributes, GROUPS};
+use super::entity::Entity;
+use super::ferror::FError as FE;
+use std::fs;
+use std::path::Path;
+
+// Attributes template - human-editable text file with character build
+//
+// [Attributes]
+// stats.strength = 6
+// skills.smallGuns = 40
+// [Modifiers]
+// skills.smallGuns = 0
+//
+// Only values are stored, so applying template keeps
+// target's esh, size1, size2 and tag wrappers intact
+
+const ATTRIBUTES_SECTION: &str = "Attributes";
+const MODIFIERS_SECTION: &str = "Modifiers";
+
+pub type TemplateValue = (String, String, String);
+
+#[derive(Default)]
+pub struct AttributesTemplate {
+    pub attributes: Vec<TemplateValue>,
+    pub modifiers: Vec<TemplateValue>,
+}
+
+impl AttributesTemplate {
+    fn collect(attrs: &Attributes, groups: &[&str]) -> Result<Vec<TemplateValue>, FE> {
+        let mut values: Vec<TemplateValue> = Vec::new();
+        for group in groups {
+            for (name, value) in attrs.get_group(group)? {
+                values.push((group.to_string(), name.to_string(), value));
+            }
+        }
+        Ok(values)
+    }
+
+    fn apply_to(
+        values: &[TemplateValue],
+        attrs: &mut Attributes,
+        groups: &[&str],
+    ) -> Result<(), FE> {
+        for (group, name, value) in values {
+            if groups.contains(&group.as_str()) {
+                attrs.set_value(group, name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn from_entity(ent: &Entity, groups: &[&str], modifiers: bool) -> Result<Self, FE> {
+        let attributes = Self::collect(&ent.get_attributes()?, groups)?;
+        let modifiers = if modifiers {
+            Self::collect(&ent.get_modifiers()?, groups)?
+        } else {
+            Vec::new()
+        };
+
+        Ok(AttributesTemplate {
+            attributes,
+            modifiers,
+        })
+    }
+
+    pub fn apply(&self, ent: &mut Entity, groups: &[&str]) -> Result<(), FE> {
+        if !self.attributes.is_empty() {
+            let mut attrs = ent.get_attributes()?;
+            Self::apply_to(&self.attributes, &mut attrs, groups)?;
+            ent.set_attributes(attrs)?;
+        }
+        if !self.modifiers.is_empty() {
+            let mut mods = ent.get_modifiers()?;
+            Self::apply_to(&self.modifiers, &mut mods, groups)?;
+            ent.set_modifiers(mods)?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, FE> {
+        let mut template = AttributesTemplate::default();
+        let mut section: Option<&str> = None;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = Some(name.trim());
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or(FE::TemplateSyntax(i + 1))?;
+            let (group, name) = key
+                .trim()
+                .split_once('.')
+                .ok_or(FE::TemplateSyntax(i + 1))?;
+            if !GROUPS.contains(&group) {
+                return Err(FE::NoAttributeGroup(group.to_string()));
+            }
+
+            let entry = (
+                group.to_string(),
+                name.to_string(),
+                value.trim().to_string(),
+            );
+            match section {
+                Some(ATTRIBUTES_SECTION) => template.attributes.push(entry),
+                Some(MODIFIERS_SECTION) => template.modifiers.push(entry),
+                _ => return Err(FE::TemplateSyntax(i + 1)),
+            }
+        }
+
+        Ok(template)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, FE> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (section, values) in [
+            (ATTRIBUTES_SECTION, &self.attributes),
+            (MODIFIERS_SECTION, &self.modifiers),
+        ] {
+            if values.is_empty() {
+                continue;
+            }
+
+            text.push_str(&format!("[{}]\n", section));
+            for (group, name, value) in values {
+                text.push_str(&format!("{}.{} = {}\n", group, name, value));
+            }
+        }
+        text
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), FE> {
+        fs::write(path, self.to_text())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(group: &str, name: &str, value: &str) -> TemplateValue {
+        (group.to_string(), name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let template = AttributesTemplate {
+            attributes: vec![
+                value("stats", "strength", "6"),
+                value("skill_tags", "smallGuns", "true"),
+            ],
+            modifiers: vec![value("skills", "smallGuns", "-5")],
+        };
+        let text = template.to_text();
+        assert_eq!(
+            text,
+            "[Attributes]\nstats.strength = 6\nskill_tags.smallGuns = true\n\
+             [Modifiers]\nskills.smallGuns = -5\n"
+        );
+
+        let parsed = AttributesTemplate::parse(&text).unwrap();
+        assert_eq!(parsed.attributes, template.attributes);
+        assert_eq!(parsed.modifiers, template.modifiers);
+    }
+
+    #[test]
+    fn empty_sections_omitted() {
+        let template = AttributesTemplate {
+            attributes: Vec::new(),
+            modifiers: vec![value("perks", "bonusMove", "1")],
+        };
+        assert_eq!(template.to_text(), "[Modifiers]\nperks.bonusMove = 1\n");
+    }
+
+    #[test]
+    fn parse_whitespace_and_comments() {
+        let text = "# build\n\n [ Attributes ] \n  stats.agility=  8 \n# end\n";
+        let parsed = AttributesTemplate::parse(text).unwrap();
+        assert_eq!(parsed.attributes, vec![value("stats", "agility", "8")]);
+        assert!(parsed.modifiers.is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let line = |text: &str| match AttributesTemplate::parse(text) {
+            Err(FE::TemplateSyntax(line)) => line,
+            _ => panic!("no syntax error in {:?}", text),
+        };
+        // value before any section
+        assert_eq!(line("stats.strength = 6"), 1);
+        assert_eq!(line("[Other]\nstats.strength = 6"), 2);
+        assert_eq!(line("[Attributes]\n\nstats.strength 6"), 3);
+        assert_eq!(line("[Attributes]\nstrength = 6"), 2);
+
+        assert!(matches!(
+            AttributesTemplate::parse("[Attributes]\nskill.smallGuns = 1"),
+            Err(FE::NoAttributeGroup(group)) if group == "skill"
+        ));
+    }
+}
@@ -6,16 +6,20 @@ use super::sgd::SGD;
 use super::ssg::SSG;
 use super::stream::{ReadStream, WriteStream};
 use super::tag::Tag;
-use deflate::deflate_bytes_zlib;
+use deflate::{deflate_bytes_zlib_conf, Compression};
 use inflate::inflate_bytes_zlib;
+use std::cell::Cell;
+use std::rc::Rc;
 
 pub struct World {
     pub offset: usize,
     pub size: usize,
 
     pub tag: Tag,
-    pub uncompressed_size: u32,
-    //pub data: Raw,
+    // updated on encode, since edits change size of world data
+    pub uncompressed_size: Cell<u32>,
+    // compression level for re-encoding, detected from original stream
+    pub compression: Compression,
     pub mission: FString,
     pub sgd: SGD,
     pub ssg: SSG,
@@ -23,10 +27,58 @@ pub struct World {
     pub entlist: EntityList,
 
     pub unparsed: Vec<u8>,
+
+    // original stream and its inflated data, reused when world is unchanged
+    compressed: Vec<u8>,
+    data: Rc<[u8]>,
+    original_compression: Compression,
 }
 
 impl World {
-    const WORLD_HDR_LEN: usize = 0x13;
+    // zlib checksum of uncompressed data
+    fn adler32(data: &[u8]) -> u32 {
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in data {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        (b << 16) | a
+    }
+
+    // length of zlib stream as it was read. World block may have padding
+    // after stream, which ends with big endian adler32 of inflated data
+    pub fn compressed_len(&self) -> usize {
+        let adler = Self::adler32(&self.data).to_be_bytes();
+        match self.compressed.windows(4).rposition(|w| w == adler) {
+            Some(pos) => pos + 4,
+            None => self.compressed.len(),
+        }
+    }
+
+    // zlib FLEVEL is 2 high bits of second header byte. deflate can't produce
+    // byte-identical stream of game's zlib, only compress at the same level
+    fn detect_compression(compressed: &[u8]) -> Compression {
+        match compressed.get(1).map(|flg| flg >> 6) {
+            Some(0) | Some(1) => Compression::Fast,
+            Some(3) => Compression::Best,
+            _ => Compression::Default,
+        }
+    }
+
+    // deflate always writes default FLEVEL, so it's patched for level
+    // to be detected on next load. FCHECK makes header multiple of 31
+    fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+        let mut stream = deflate_bytes_zlib_conf(data, compression);
+        let flevel: u8 = match compression {
+            Compression::Fast => 0,
+            Compression::Default => 2,
+            Compression::Best => 3,
+        };
+        let flg = (flevel << 6) | (stream[1] & 0x20);
+        let fcheck = (31 - (stream[0] as u16 * 256 + flg as u16) % 31) % 31;
+        stream[1] = flg | fcheck as u8;
+        stream
+    }
 }
 
 pub type WorldOffsetSize = (usize, usize);
@@ -39,14 +91,33 @@ impl DecoderCtx<WorldOffsetSize, ()> for World {
         let uncompressed_size = enc.read_u32()?;
         enc.skip(4);
 
-        let data = inflate_bytes_zlib(enc.as_bytes(size)?).map_err(|e| FE::DeflateError(e))?;
-        let mut rd = ReadStream::new(&data, 0);
+        // size spans whole world block from tag, so stream with its padding
+        // is what follows header
+        let compressed = enc
+            .as_bytes(size.saturating_sub(enc.offset() - offset))?
+            .to_vec();
+        let compression = Self::detect_compression(&compressed);
+        let data: Rc<[u8]> = inflate_bytes_zlib(&compressed)
+            .map_err(FE::DeflateError)?
+            .into();
+        let mut rd = ReadStream::new(&data, 0)
+            .with_codepage(enc.codepage())
+            .with_lazy(enc.is_lazy())
+            .with_shared(data.clone());
 
-        let mission: FString = rd.read()?;
-        let sgd: SGD = rd.read()?;
-        let ssg: SSG = rd.read()?;
+        let mission: FString = rd
+            .read()
+            .map_err(|fe| fe.world_context("mission", rd.offset()))?;
+        let sgd: SGD = rd
+            .read()
+            .map_err(|fe| fe.world_context("SGD", rd.offset()))?;
+        let ssg: SSG = rd
+            .read()
+            .map_err(|fe| fe.world_context("SSG", rd.offset()))?;
 
-        let entlist: EntityList = rd.read_ctx(EntityEncoding::World)?;
+        let entlist: EntityList = rd
+            .read_ctx(EntityEncoding::World)
+            .map_err(|fe| fe.world_context("EntityList", rd.offset()))?;
 
         let unparsed = rd.read_bytes(data.len() - rd.offset())?;
 
@@ -54,19 +125,22 @@ impl DecoderCtx<WorldOffsetSize, ()> for World {
             offset,
             size,
             tag,
-            uncompressed_size,
-            //data,
+            uncompressed_size: Cell::new(uncompressed_size),
+            compression,
             mission,
             sgd,
             ssg,
             entlist,
             unparsed,
+            compressed,
+            data,
+            original_compression: compression,
         })
     }
 
     fn encode(&self, wd: &mut WriteStream, _: ()) -> Result<(), FE> {
         let data = {
-            let mut wd = WriteStream::new(self.uncompressed_size as usize);
+            let mut wd = WriteStream::new(self.uncompressed_size.get() as usize);
 
             wd.write(&self.mission)?;
             wd.write(&self.sgd)?;
@@ -75,18 +149,215 @@ impl DecoderCtx<WorldOffsetSize, ()> for World {
             wd.write_bytes(&self.unparsed);
 
             let raw = wd.into_raw(0, 0);
-            deflate_bytes_zlib(&raw.mem)
+            self.uncompressed_size.set(raw.mem.len() as u32);
+            if raw.mem[..] == self.data[..] && self.compression == self.original_compression {
+                self.compressed.clone()
+            } else {
+                Self::compress(&raw.mem, self.compression)
+            }
         };
 
         wd.write(&self.tag)?;
-        wd.write_u32(self.uncompressed_size)?;
-        wd.write_u32(self.uncompressed_size)?;
+        wd.write_u32(self.uncompressed_size.get())?;
+        wd.write_u32(self.uncompressed_size.get())?;
         wd.write_bytes(&data);
 
         Ok(())
     }
 
     fn get_enc_size(&self) -> usize {
-        Self::WORLD_HDR_LEN + self.size
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ssg::SSG_DATA_SIZE;
+    use super::*;
+
+    const LEVELS: [Compression; 3] = [Compression::Fast, Compression::Default, Compression::Best];
+
+    // mission, SGD without dialogs, SSG, entity list without entities and tail
+    fn world_data() -> Vec<u8> {
+        let mut entlist: Vec<u8> = b"<entity_file>\x004\0".to_vec();
+        entlist.extend(0u32.to_le_bytes());
+        entlist.extend(1u16.to_le_bytes());
+        entlist.extend(0u32.to_le_bytes());
+        world_data_with(&entlist)
+    }
+
+    fn world_data_with(entlist: &[u8]) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend(9u32.to_le_bytes());
+        data.extend(b"Mission01");
+        data.extend(b"<sgd>\x001\0");
+        data.extend([0u8; 0x48]);
+        data.extend([0u8; 8]);
+        data.extend(b"<ssg>\x001\0");
+        data.extend([7u8; SSG_DATA_SIZE]);
+        data.extend(entlist);
+        data.extend(b"TAILDATA".repeat(16));
+        data
+    }
+
+    // zlib stream with single stored block, which deflate never produces
+    // for compressible data
+    fn stored_stream(data: &[u8]) -> Vec<u8> {
+        let mut stream: Vec<u8> = vec![0x78, 0x01, 0x01];
+        stream.extend((data.len() as u16).to_le_bytes());
+        stream.extend((!(data.len() as u16)).to_le_bytes());
+        stream.extend(data);
+        stream.extend(World::adler32(data).to_be_bytes());
+        stream
+    }
+
+    fn world_block(stream: &[u8], size: usize) -> Vec<u8> {
+        let mut block: Vec<u8> = b"<world>\x009\0".to_vec();
+        block.extend((size as u32).to_le_bytes());
+        block.extend((size as u32).to_le_bytes());
+        block.extend(stream);
+        block
+    }
+
+    fn decode(block: &[u8]) -> World {
+        decode_lazy(block, false)
+    }
+
+    fn decode_lazy(block: &[u8], lazy: bool) -> World {
+        let mut rd = ReadStream::new(block, 0).with_lazy(lazy);
+        World::decode(&mut rd, (0, block.len())).unwrap()
+    }
+
+    fn prop(data: &mut Vec<u8>, name: &[u8], data_type: u32, value: &[u8]) {
+        data.extend((name.len() as u32).to_le_bytes());
+        data.extend(name);
+        data.extend(data_type.to_le_bytes());
+        data.extend((value.len() as u32).to_le_bytes());
+        data.extend(value);
+    }
+
+    // two types, entities with ESH of several value types and an empty slot
+    fn entities() -> Vec<u8> {
+        let mut data: Vec<u8> = b"<entity_file>\x004\0".to_vec();
+        data.extend(2u32.to_le_bytes());
+        for type_name in [&b"Character"[..], b"Weapon_Pistol"] {
+            data.extend((type_name.len() as u32).to_le_bytes());
+            data.extend(type_name);
+        }
+        data.extend(4u16.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+
+        data.extend(0x10u32.to_le_bytes());
+        data.extend(0u16.to_le_bytes());
+        data.extend(b"<esh>\x002\0");
+        data.extend(4u32.to_le_bytes());
+        // ANSI string, int, link and bool with WCS2 name
+        prop(&mut data, b"Display Name", 4, b"\x04\0\0\0J\x98o!");
+        prop(&mut data, b"hitPoints", 3, &25i32.to_le_bytes());
+        prop(&mut data, b"Hand1", 12, &[2, 0, 0x10, 0]);
+        data.extend((3u32 | 1 << 31).to_le_bytes());
+        data.extend(b"O\0k\0!\0");
+        data.extend(1u32.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+        data.push(1);
+
+        data.extend(0u32.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(b"<esh>\x002\0");
+        data.extend(2u32.to_le_bytes());
+        prop(&mut data, b"Owner", 12, &[1, 0, 0, 0]);
+        prop(&mut data, b"Blob", 0x77, &[1, 2, 3]);
+
+        data.extend(0u32.to_le_bytes());
+        data.extend(0xFFFFu16.to_le_bytes());
+        data
+    }
+
+    fn listing(entlist: &EntityList) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        for (id, ent) in entlist {
+            lines.push(format!("{} {} {}", id, ent.type_idx, ent.has_esh()));
+            if let Some(esh) = ent.esh().unwrap() {
+                for (name, value) in &esh.props {
+                    lines.push(format!("{}={}", name, value));
+                }
+            }
+        }
+        lines
+    }
+
+    fn encode_entlist(entlist: &EntityList) -> Vec<u8> {
+        let mut wd = WriteStream::new(0);
+        wd.write_ctx(entlist, EntityEncoding::World).unwrap();
+        wd.into_vec()
+    }
+
+    fn encode(world: &World) -> Vec<u8> {
+        let mut wd = WriteStream::new(world.get_enc_size());
+        wd.write_ctx(world, ()).unwrap();
+        wd.into_vec()
+    }
+
+    #[test]
+    fn lazy_decode_matches_eager() {
+        let entities = entities();
+        let data = world_data_with(&entities);
+        let block = world_block(&stored_stream(&data), data.len());
+
+        let eager = decode_lazy(&block, false);
+        let mut lazy = decode_lazy(&block, true);
+        assert_eq!(encode_entlist(&eager.entlist), entities);
+        assert_eq!(encode_entlist(&lazy.entlist), entities);
+
+        assert_eq!(listing(&lazy.entlist), listing(&eager.entlist));
+        assert_eq!(listing(&eager.entlist).len(), 3 + 4 + 2);
+
+        // entity decoded on access for editing still encodes the same
+        lazy.entlist
+            .get_entity_mut(2)
+            .unwrap()
+            .get_esh_mut()
+            .unwrap();
+        assert_eq!(encode_entlist(&lazy.entlist), entities);
+        assert_eq!(encode(&eager), block);
+        assert_eq!(encode(&lazy), block);
+    }
+
+    #[test]
+    fn compression_levels_have_valid_header() {
+        let data = world_data();
+        for compression in LEVELS {
+            let stream = World::compress(&data, compression);
+            assert_eq!((stream[0] as u16 * 256 + stream[1] as u16) % 31, 0);
+            assert_eq!(World::detect_compression(&stream), compression);
+            assert_eq!(inflate_bytes_zlib(&stream).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn unchanged_world_is_written_back() {
+        let data = world_data();
+        let block = world_block(&stored_stream(&data), data.len());
+        let world = decode(&block);
+        assert_eq!(world.compressed_len(), block.len() - 0x12);
+        assert_eq!(encode(&world), block);
+    }
+
+    #[test]
+    fn changed_compression_recompresses() {
+        let data = world_data();
+        let block = world_block(&stored_stream(&data), data.len());
+        // stored stream has FLEVEL of Fast, so Fast would reuse it
+        for compression in [Compression::Default, Compression::Best] {
+            let mut world = decode(&block);
+            world.compression = compression;
+
+            let encoded = encode(&world);
+            assert_ne!(encoded, block);
+            let stream = &encoded[0x12..];
+            assert_eq!((stream[0] as u16 * 256 + stream[1] as u16) % 31, 0);
+            assert_eq!(World::detect_compression(stream), compression);
+            assert_eq!(inflate_bytes_zlib(stream).unwrap(), data);
+        }
     }
 }
@@ -1,15 +1,30 @@
 #![deny(rust_2018_idioms)]
+#![allow(clippy::upper_case_acronyms)]
 use clap::{Parser, Subcommand, ValueEnum};
-use std::collections::HashMap;
-use std::io::{stdout, BufWriter, Write};
+use deflate::Compression;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{stdout, BufWriter, ErrorKind, Read, Write};
 use std::path::Path;
 
 mod fot;
-use fot::attributes::Attributes;
+use fot::attributes::{Attributes, GROUPS};
+use fot::dialogs::Dialogs;
 use fot::esh::{ESH, ESHValue};
 use fot::entity::Entity;
-use fot::entitylist::EntityList;
+use fot::entitylist::{EntityList, DEFAULT_ENTITY_TAG};
+use fot::tag::Tag;
+use fot::ferror::FError as FE;
+use fot::flags::{parse_flag, EntityFlags, LinkFlags};
+use fot::fstring::{codepage_for_name, CodePage};
+use fot::refgraph::{RefGraph, NO_ENTITY};
+use fot::inventory;
 use fot::save::Save;
+use fot::sgd::SGD;
+use fot::ssg::SSG;
+use fot::sheet::CharacterSheet;
+use fot::template::AttributesTemplate;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -18,18 +33,35 @@ struct Cli {
     #[arg(short, long)]
     input: String,
 
-    // Specify save file or ent file type
-    //#[arg(value_enum)]
-    //kind: Kind,
+    /// Input file type, detected from file tag or extension by default
+    #[arg(long, value_enum)]
+    kind: Option<Kind>,
+
+    /// Code page of ANSI strings, depends on game localization (i.e. 1250, 1251, 1252)
+    #[arg(long, default_value = "1251", value_parser = parse_codepage)]
+    codepage: CodePage,
+
+    /// Decode entity values only when a command reads them, faster for large saves
+    #[arg(long)]
+    lazy: bool,
+
+    /// World compression level of saved file, same as original by default.
+    /// Unchanged world keeps its original compressed data
+    #[arg(long, value_enum)]
+    compression: Option<CompressionLevel>,
 
     /// Output file path
     #[arg(short, long)]
     output: String,
 
-    /// Selected entities ids
+    /// Selected entities ids and ranges (i.e. 1,5,10-20), ranges skip empty slots, !id or !first-last excludes entities
     #[arg(long)]
     ids: Option<String>,
 
+    /// Select entities by type name glob (i.e. *Weapon*), also filters --ids and --find
+    #[arg(long = "type")]
+    type_glob: Option<String>,
+
     /// key=value pairs to find entities (i.e. key1=value1,key2=value2 will return entities with one of the matching pairs)
     #[arg(long)]
     find: Option<String>,
@@ -44,10 +76,141 @@ enum Kind {
     Ent,
 }
 
+impl Kind {
+    fn detect(path: &Path) -> Kind {
+        let mut magic = vec![0; DEFAULT_ENTITY_TAG.name.len()];
+        if let Ok(mut file) = File::open(path) {
+            if file.read_exact(&mut magic).is_ok() && magic == DEFAULT_ENTITY_TAG.name.as_bytes() {
+                return Kind::Ent;
+            }
+        }
+
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("ent") => Kind::Ent,
+            _ => Kind::Save,
+        }
+    }
+}
+
+fn parse_codepage(name: &str) -> Result<CodePage, String> {
+    codepage_for_name(name).ok_or_else(|| format!("unknown code page {}", name))
+}
+
+// file being edited - either whole save or standalone entity file
+enum Document {
+    Save(Box<Save>),
+    Ent(EntityList),
+}
+
+impl Document {
+    fn load(path: &Path, kind: Option<Kind>, codepage: CodePage, lazy: bool) -> Result<Self, FE> {
+        Ok(match kind.unwrap_or_else(|| Kind::detect(path)) {
+            Kind::Save => Document::Save(Box::new(Save::load(path, codepage, lazy)?)),
+            Kind::Ent => Document::Ent(EntityList::load_entfile(path, codepage)?),
+        })
+    }
+
+    // .ent files are not compressed
+    fn set_compression(&mut self, level: Option<CompressionLevel>) {
+        if let (Document::Save(save), Some(level)) = (self, level) {
+            save.world.compression = level.into();
+        }
+    }
+
+    fn entlist(&self) -> &EntityList {
+        match self {
+            Document::Save(save) => &save.world.entlist,
+            Document::Ent(entlist) => entlist,
+        }
+    }
+
+    fn entlist_mut(&mut self) -> &mut EntityList {
+        match self {
+            Document::Save(save) => &mut save.world.entlist,
+            Document::Ent(entlist) => entlist,
+        }
+    }
+
+    // dialogs are stored only in saves
+    fn sgd(&self) -> Result<&SGD, FE> {
+        match self {
+            Document::Save(save) => Ok(&save.world.sgd),
+            Document::Ent(_) => Err(FE::NoWorld),
+        }
+    }
+
+    fn sgd_mut(&mut self) -> Result<&mut SGD, FE> {
+        match self {
+            Document::Save(save) => Ok(&mut save.world.sgd),
+            Document::Ent(_) => Err(FE::NoWorld),
+        }
+    }
+
+    fn ssg(&self) -> Result<&SSG, FE> {
+        match self {
+            Document::Save(save) => Ok(&save.world.ssg),
+            Document::Ent(_) => Err(FE::NoWorld),
+        }
+    }
+
+    fn ssg_mut(&mut self) -> Result<&mut SSG, FE> {
+        match self {
+            Document::Save(save) => Ok(&mut save.world.ssg),
+            Document::Ent(_) => Err(FE::NoWorld),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), FE> {
+        match self {
+            Document::Save(save) => save.save(path),
+            Document::Ent(entlist) => entlist.save_entfile(path),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+enum CompressionLevel {
+    Fast,
+    Default,
+    Best,
+}
+
+impl From<CompressionLevel> for Compression {
+    fn from(level: CompressionLevel) -> Self {
+        match level {
+            CompressionLevel::Fast => Compression::Fast,
+            CompressionLevel::Default => Compression::Default,
+            CompressionLevel::Best => Compression::Best,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+enum SheetFormat {
+    Text,
+    Markdown,
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+enum DialogFormat {
+    Po,
+    Csv,
+}
+
+impl DialogFormat {
+    fn detect(path: &Path) -> DialogFormat {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => DialogFormat::Csv,
+            _ => DialogFormat::Po,
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     ListEntities,
-    /// Find entities, kv = key1=value,key2=value2
+    /// Find entities, kv = key1=value,key2=value2, narrowed by --type
     FindEntities,
     /// List ESH values of selected entities
     ListValues,
@@ -82,65 +245,288 @@ enum Commands {
         name: String,
         value: String,
     },
+    /// Clone selected entities with entities they own, new entities get next free ids
+    CloneEntity {
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+    /// Import entities from .ent file into world, new entities get next free ids
+    ImportEntity {
+        path: String,
+        /// Position a,b,c for entity Frame
+        #[arg(long)]
+        position: Option<String>,
+    },
+    /// Copy selected entities with every entity they reference into destination file
+    TransferEntity {
+        destination: String,
+    },
+    /// List what selected entities reference and what references them
+    ListReferences,
+    /// Find references to entities that don't exist or are empty
+    CheckReferences,
+    /// Export entity reference graph as Graphviz DOT
+    ExportGraph {
+        path: String,
+    },
+    /// List items in inventory container of selected characters. Items are
+    /// container's EntityFlags links, count and condition are not shown
+    ListInventory,
+    /// Give selected characters a copy of item entity. Copy is linked from
+    /// an empty link of inventory container, item's own links are not changed
+    GiveItem {
+        item: usize,
+    },
+    /// Take item from inventory of selected characters, moving it to another owner or removing it.
+    /// Only container links are changed
+    TakeItem {
+        item: usize,
+        /// New owner entity id
+        #[arg(long)]
+        to: Option<usize>,
+    },
+    /// List entity types with number of entities of each type
+    ListTypes {
+        /// Drop types that no entity uses
+        #[arg(long)]
+        remove_unused: bool,
+    },
+    /// Set entity flags by 0x hex mask, or flags of EntityFlags value when --link is given
+    SetFlag {
+        flag: String,
+        /// ESH EntityFlags value name
+        #[arg(long)]
+        link: Option<String>,
+    },
+    /// Clear entity flags by 0x hex mask, or flags of EntityFlags value when --link is given
+    ClearFlag {
+        flag: String,
+        /// ESH EntityFlags value name
+        #[arg(long)]
+        link: Option<String>,
+    },
+    /// Remove selected entities, leaving empty slots so other ids stay the same
+    RemoveEntity,
+    /// Add experience points, leveling up entity like game does (skill points, perks, hit points)
+    AddXp {
+        xp: u32,
+    },
+    /// Print character sheet - SPECIAL, effective skills, traits, perks, addictions and current HP
+    CharacterSheet {
+        #[arg(long, value_enum, default_value_t = SheetFormat::Text)]
+        format: SheetFormat,
+    },
+    /// Export attributes (and optionally modifiers) of selected entity into editable template file
+    ExportAttributes {
+        path: String,
+        /// Comma-separated groups to export (i.e. stats,skills), all groups by default
+        #[arg(long)]
+        groups: Option<String>,
+        /// Also export modifiers
+        #[arg(long)]
+        modifiers: bool,
+    },
+    /// Apply attributes template file to selected entities
+    ImportAttributes {
+        path: String,
+        /// Comma-separated groups to apply (i.e. skills), all groups by default
+        #[arg(long)]
+        groups: Option<String>,
+    },
+    /// Export dialog lines of save (speech bubbles) for translation, keyed by speaker and index
+    ExportDialogs {
+        path: String,
+        /// File format, detected from extension by default
+        #[arg(long, value_enum)]
+        format: Option<DialogFormat>,
+    },
+    /// Import translated dialog lines back into save, lines without translation are kept
+    ImportDialogs {
+        path: String,
+        /// File format, detected from extension by default
+        #[arg(long, value_enum)]
+        format: Option<DialogFormat>,
+    },
+    /// Print summary of save - header, mission, tags, sizes, entity types and party members
+    Info,
+    /// Hex dump SSG block of save with its offset in uncompressed world data
+    ShowSsg {
+        /// Write u32 value at byte position in block and save (i.e. 0x8=100)
+        #[arg(long)]
+        set: Option<String>,
+    },
+}
+
+// exit codes, so wrapper scripts can tell failures apart.
+// Bad arguments use same code as clap's usage errors
+const EXIT_FAILURE: i32 = 1;
+const EXIT_BAD_ARGUMENTS: i32 = 2;
+const EXIT_NO_ENTITIES: i32 = 3;
+const EXIT_DECODE_FAILURE: i32 = 4;
+const EXIT_WRITE_FAILURE: i32 = 5;
+
+fn exit_code(fe: &FE) -> i32 {
+    match fe {
+        FE::InvalidValue(_)
+        | FE::UnknownFlag(_)
+        | FE::NoAttributeGroup(_)
+        | FE::NoAttribute(_)
+        | FE::TemplateSyntax(_)
+        | FE::DialogSyntax(_)
+        | FE::NoEntitySelector => EXIT_BAD_ARGUMENTS,
+        // missing input file is bad argument, other IO errors are not
+        FE::IOError(e) if e.kind() == ErrorKind::NotFound => EXIT_BAD_ARGUMENTS,
+        FE::NoEntity(_)
+        | FE::NoMatchingEntities
+        | FE::NotSingleEntity(_)
+        | FE::NotInInventory(_, _)
+        | FE::NoInventory(_)
+        | FE::NoDialogLine(_, _)
+        | FE::EntityNoESH
+        | FE::NoESHValue => EXIT_NO_ENTITIES,
+        FE::Decode(_, _, _)
+        | FE::Utf8Error(_)
+        | FE::DeflateError(_)
+        | FE::NoWorld
+        | FE::NoCampaign
+        | FE::UnknownWorldSize
+        | FE::StreamOverflow(_, _, _)
+        | FE::NoZeroTerminator
+        | FE::ESHValueNonBinary
+        | FE::AttributesNonBinary
+        | FE::ValueNoESBIN
+        | FE::NoType(_)
+        | FE::SGDCountMismatch(_, _) => EXIT_DECODE_FAILURE,
+        FE::WriteFailed(_, _) => EXIT_WRITE_FAILURE,
+        _ => EXIT_FAILURE,
+    }
 }
 
-fn log_entities<'a>(entlist: &EntityList, iter: impl IntoIterator<Item = (usize, &'a Entity)>) {
+fn out(e: std::io::Error) -> FE {
+    FE::WriteFailed("stdout".to_string(), Box::new(e.into()))
+}
+
+fn write_failed(path: &str) -> impl FnOnce(FE) -> FE + '_ {
+    move |fe| FE::WriteFailed(path.to_string(), Box::new(fe))
+}
+
+fn save_output(doc: &Document, path: &str) -> Result<(), FE> {
+    doc.save(Path::new(path)).map_err(write_failed(path))
+}
+
+fn log_entities<'a>(
+    entlist: &EntityList,
+    iter: impl IntoIterator<Item = (usize, &'a Entity)>,
+) -> Result<(), FE> {
     let mut bf = BufWriter::new(stdout().lock());
     for (id, ent) in iter {
         let type_name = if ent.type_idx != 0xFFFF {
-            entlist.get_type_name(ent.type_idx).str.as_str()
+            entlist.get_type_name(ent.type_idx).as_str()
         } else {
             "<no type>"
         };
-        write!(bf, "{}\t{}\n", id, type_name).expect("failed to write stdout");
+        writeln!(bf, "{}\t{}\t{}", id, type_name, ent.get_flags()).map_err(out)?;
     }
+    Ok(())
 }
 
-fn parse_kv(kv: &String) -> Vec<(&str, &str)> {
+fn parse_kv(kv: &str) -> Result<Vec<(&str, &str)>, FE> {
     kv.split(",")
         .map(|kv| kv.split_once("="))
         .collect::<Option<Vec<(&str, &str)>>>()
-        .unwrap()
+        .ok_or_else(|| FE::InvalidValue(kv.to_string()))
 }
 
-fn from_ids(entlist: &EntityList, line: String) -> HashMap<usize, &Entity> {
-    line.split(",")
-        .map(|id| {
-            (
-                id.parse::<usize>().expect("parse id"),
-                entlist.get_entity(id.parse().expect("parse id")),
-            )
-        })
-        .collect::<HashMap<usize, &Entity>>()
+fn parse_id(id: &str) -> Result<usize, FE> {
+    id.trim()
+        .parse()
+        .map_err(|_| FE::InvalidValue(id.to_string()))
 }
 
-fn from_ids_mut(entlist: &mut EntityList, line: String) -> HashMap<usize, &mut Entity> {
+// comma separated ids and ranges (i.e. 1,5,10-20), entries starting with !
+// are excluded, and only exclusions select every entity except them
+fn parse_ids(entlist: &EntityList, line: &str) -> Result<Vec<usize>, FE> {
+    let mut include: Vec<(usize, usize)> = Vec::new();
+    let mut exclude: Vec<(usize, usize)> = Vec::new();
+    for part in line.split(",") {
+        let part = part.trim();
+        let (list, range) = match part.strip_prefix("!") {
+            Some(range) => (&mut exclude, range),
+            None => (&mut include, part),
+        };
+        let (first, last) = match range.split_once("-") {
+            Some((first, last)) => (parse_id(first)?, parse_id(last)?),
+            None => (parse_id(range)?, parse_id(range)?),
+        };
+        if first > last {
+            return Err(FE::InvalidValue(part.to_string()));
+        }
+        list.push((first, last));
+    }
+
+    let mut ids: Vec<usize> = Vec::new();
+    if include.is_empty() {
+        ids.extend(
+            entlist
+                .into_iter()
+                .filter(|(_, ent)| !ent.is_empty())
+                .map(|(id, _)| id),
+        );
+    }
+    for (first, last) in include {
+        for id in first..=last {
+            let ent = entlist.get_entity(id)?;
+            // ranges skip empty slots, single ids select them as given
+            if first == last || !ent.is_empty() {
+                ids.push(id);
+            }
+        }
+    }
+
+    ids.retain(|id| {
+        !exclude
+            .iter()
+            .any(|&(first, last)| (first..=last).contains(id))
+    });
+    ids.sort();
+    ids.dedup();
+    Ok(ids)
+}
+
+fn from_ids(entlist: &EntityList, line: String) -> Result<HashMap<usize, &Entity>, FE> {
+    let mut entities: HashMap<usize, &Entity> = HashMap::new();
+    for id in parse_ids(entlist, &line)? {
+        entities.insert(id, entlist.get_entity(id)?);
+    }
+    Ok(entities)
+}
+
+fn from_ids_mut(
+    entlist: &mut EntityList,
+    line: String,
+) -> Result<HashMap<usize, &mut Entity>, FE> {
     let mut entities: HashMap<usize, &mut Entity> = HashMap::new();
-    let ids: Vec<usize> = line
-        .split(",")
-        .map(|id| id.parse().expect("id parse"))
-        .collect();
-    
+    let ids: HashSet<usize> = parse_ids(entlist, &line)?.into_iter().collect();
     for (id, ent) in entlist {
         if ids.contains(&id) {
             entities.insert(id, ent);
         }
     }
 
-    entities
+    Ok(entities)
 }
 
-fn find_entities(entlist: &EntityList, line: String) -> HashMap<usize, &Entity> {
-    let kv = parse_kv(&line);
+fn find_entities(entlist: &EntityList, line: String) -> Result<HashMap<usize, &Entity>, FE> {
+    let kv = parse_kv(&line)?;
     let mut entities: HashMap<usize, &Entity> = HashMap::new();
     for (id, ent) in entlist {
-        let esh = match &ent.esh {
+        let esh = match ent.esh()? {
             Some(esh) => esh,
             None => continue,
         };
 
         for (name, value) in &esh.props {
-            let key = name.str.as_str();
+            let key = name.as_str();
             let svalue = value.to_string();
             for (k, v) in &kv {
                 if key == *k && svalue == *v {
@@ -150,20 +536,23 @@ fn find_entities(entlist: &EntityList, line: String) -> HashMap<usize, &Entity>
         }
     }
 
-    entities
+    Ok(entities)
 }
 
-fn find_entities_mut(entlist: &mut EntityList, line: String) -> HashMap<usize, &mut Entity> {
-    let kv = parse_kv(&line);
+fn find_entities_mut(
+    entlist: &mut EntityList,
+    line: String,
+) -> Result<HashMap<usize, &mut Entity>, FE> {
+    let kv = parse_kv(&line)?;
     let mut entities: HashMap<usize, &mut Entity> = HashMap::new();
     for (id, ent) in entlist {
-        let esh = match &ent.esh {
+        let esh = match ent.esh()? {
             Some(esh) => esh,
             None => continue,
         };
 
         'check: for (name, value) in &esh.props {
-            let key = name.str.as_str();
+            let key = name.as_str();
             let svalue = value.to_string();
             for (k, v) in &kv {
                 if key == *k && svalue == *v {
@@ -174,226 +563,1083 @@ fn find_entities_mut(entlist: &mut EntityList, line: String) -> HashMap<usize, &
         }
     }
 
-    entities
+    Ok(entities)
+}
+
+// glob with * and ? wildcards
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((spi, sti)) = star {
+            pi = spi + 1;
+            ti = sti + 1;
+            star = Some((spi, sti + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+fn find_types(entlist: &EntityList, glob: &str) -> Vec<usize> {
+    entlist
+        .get_types()
+        .iter()
+        .enumerate()
+        .filter(|(_, type_name)| glob_match(glob, type_name.as_str()))
+        .map(|(idx, _)| idx)
+        .collect()
 }
 
 fn get_entities(
     entlist: &EntityList,
     ids: Option<String>,
     find: Option<String>,
-) -> HashMap<usize, &Entity> {
-    if let Some(ids) = ids {
-        from_ids(entlist, ids)
+    type_glob: Option<String>,
+) -> Result<HashMap<usize, &Entity>, FE> {
+    let types = type_glob.map(|glob| find_types(entlist, &glob));
+    let mut entities = if let Some(ids) = ids {
+        from_ids(entlist, ids)?
     } else if let Some(find) = find {
-        find_entities(entlist, find)
+        find_entities(entlist, find)?
+    } else if types.is_some() {
+        entlist.into_iter().collect()
     } else {
-        panic!("No entity selector provided!")
+        return Err(FE::NoEntitySelector);
+    };
+
+    if let Some(types) = types {
+        entities.retain(|_, ent| !ent.is_empty() && types.contains(&ent.type_idx));
     }
+    if entities.is_empty() {
+        return Err(FE::NoMatchingEntities);
+    }
+    Ok(entities)
 }
 
 fn get_entities_mut(
     entlist: &mut EntityList,
     ids: Option<String>,
     find: Option<String>,
-) -> HashMap<usize, &mut Entity> {
-    if let Some(ids) = ids {
-        from_ids_mut(entlist, ids)
+    type_glob: Option<String>,
+) -> Result<HashMap<usize, &mut Entity>, FE> {
+    let types = type_glob.map(|glob| find_types(entlist, &glob));
+    let mut entities = if let Some(ids) = ids {
+        from_ids_mut(entlist, ids)?
     } else if let Some(find) = find {
-        find_entities_mut(entlist, find)
+        find_entities_mut(entlist, find)?
+    } else if types.is_some() {
+        entlist.into_iter().collect()
     } else {
-        panic!("No entity selector provided!")
+        return Err(FE::NoEntitySelector);
+    };
+
+    if let Some(types) = types {
+        entities.retain(|_, ent| !ent.is_empty() && types.contains(&ent.type_idx));
+    }
+    if entities.is_empty() {
+        return Err(FE::NoMatchingEntities);
     }
+    Ok(entities)
 }
 
-fn log_esh(esh: &ESH) {
+fn log_esh(esh: &ESH) -> Result<(), FE> {
     let mut bf = BufWriter::new(stdout().lock());
     for (name, value) in &esh.props {
-        write!(bf, "{}\t{}\n", name, value).expect("stdout");
+        writeln!(bf, "{}\t{}", name, value).map_err(out)?;
     }
-    write!(bf, "\n").expect("stdout");
+    writeln!(bf).map_err(out)?;
+    Ok(())
 }
 
-fn list_values(ent: &Entity) {
-    let esh = match ent.esh.as_ref() {
+fn list_values(ent: &Entity) -> Result<(), FE> {
+    let esh = match ent.esh()? {
         Some(esh) => esh,
-        None => return
+        None => return Ok(()),
     };
 
-    log_esh(esh);
+    log_esh(esh)
 }
 
-fn write_esh(esh: &mut ESH, name: &String, value: &String) {
+fn write_esh(esh: &mut ESH, name: &str, value: &str) -> Result<(), FE> {
     use ESHValue as EV;
-    match esh.props.get_mut(name.as_str()).unwrap() {
-        EV::Bool(val) => *val = value.parse().expect("parse"),
-        EV::Float(val) => *val = value.parse().expect("parse"),
-        EV::Int(val) => *val = value.parse().expect("parse"),
-        EV::String(val) => val.str = value.clone(),
-        EV::Sprite(val) => val.str = value.clone(),
-        EV::Enum(val) => val.str = value.clone(),
-        EV::EntityFlags(val) => val.entity_id = value.parse().expect("parse"),
-        _ => panic!("unsupported ESH type input")
+    let invalid = || FE::InvalidValue(value.to_string());
+    match esh.props.get_mut(name).ok_or(FE::NoESHValue)? {
+        EV::Bool(val) => *val = value.parse().map_err(|_| invalid())?,
+        EV::Float(val) => *val = value.parse().map_err(|_| invalid())?,
+        EV::Int(val) => *val = value.parse().map_err(|_| invalid())?,
+        EV::String(val) => val.set_str(value.to_string()),
+        EV::Sprite(val) => val.set_str(value.to_string()),
+        EV::Enum(val) => val.set_str(value.to_string()),
+        EV::EntityFlags(val) => val.entity_id = value.parse().map_err(|_| invalid())?,
+        _ => return Err(FE::InvalidValue(format!("unsupported ESH type of {}", name))),
     }
+    Ok(())
 }
 
-fn write_value(ent: &mut Entity, name: &String, value: &String) {
-    let esh = ent.get_esh_mut().expect("failed to get esh");
-    write_esh(esh, name, value);
+fn write_value(ent: &mut Entity, name: &str, value: &str) -> Result<(), FE> {
+    write_esh(ent.get_esh_mut()?, name, value)
 }
 
-fn read_nested(ent: &Entity, nested: &String) {
-    let esh = ent.get_esh().expect("failed to get esh");
-    let nested_esh = esh.get_nested(nested.as_str()).expect("failed to get nested");
-    log_esh(&nested_esh);
+fn read_nested(ent: &Entity, nested: &str) -> Result<(), FE> {
+    log_esh(&ent.get_esh()?.get_nested(nested)?)
 }
 
-fn write_nested(ent: &mut Entity, nested: &String, name: &String, value: &String) {
-    let esh = ent.get_esh_mut().expect("failed to get esh");
-    let mut nested_esh = esh.get_nested(nested.as_str()).expect("failed to get nested");
-    write_esh(&mut nested_esh, name, value);
-    esh.set_nested(nested.as_str(), nested_esh).expect("failed to set nested esh");
+fn write_nested(ent: &mut Entity, nested: &str, name: &str, value: &str) -> Result<(), FE> {
+    let esh = ent.get_esh_mut()?;
+    let mut nested_esh = esh.get_nested(nested)?;
+    write_esh(&mut nested_esh, name, value)?;
+    esh.set_nested(nested, nested_esh)
 }
 
-fn log_attributes(attrs: Attributes) {
+fn log_attributes(attrs: Attributes) -> Result<(), FE> {
     let mut bf = BufWriter::new(stdout().lock());
 
-    write!(bf, "stats\n").expect("stdout");
+    writeln!(bf, "stats").map_err(out)?;
     for (name, value) in attrs.stats {
-        write!(bf, "\t{}\t{}\n", name, value).expect("stdout");
+        writeln!(bf, "\t{}\t{}", name, value).map_err(out)?;
     }
-    write!(bf, "traits\n").expect("stdout");
+    writeln!(bf, "traits").map_err(out)?;
     for (name, value) in attrs.traits {
-        write!(bf, "\t{}\t{}\n", name, value).expect("stdout");
+        writeln!(bf, "\t{}\t{}", name, value).map_err(out)?;
     }
-    write!(bf, "derived\n").expect("stdout");
+    writeln!(bf, "derived").map_err(out)?;
     for (name, value) in attrs.derived {
-        write!(bf, "\t{}\t{}\n", name, value).expect("stdout");
+        writeln!(bf, "\t{}\t{}", name, value).map_err(out)?;
     }
-    write!(bf, "skills\n").expect("stdout");
+    writeln!(bf, "skills").map_err(out)?;
     for (name, value) in attrs.skills {
-        write!(bf, "\t{}\t{}\n", name, value).expect("stdout");
+        writeln!(bf, "\t{}\t{}", name, value).map_err(out)?;
     }
-    write!(bf, "skill_tags\n").expect("stdout");
+    writeln!(bf, "skill_tags").map_err(out)?;
     for (name, value) in attrs.skill_tags {
-        write!(bf, "\t{}\t{}\n", name, value).expect("stdout");
+        writeln!(bf, "\t{}\t{}", name, value).map_err(out)?;
     }
-    write!(bf, "opt_traits\n").expect("stdout");
+    writeln!(bf, "opt_traits").map_err(out)?;
     for (name, value) in attrs.opt_traits {
-        write!(bf, "\t{}\t{}\n", name, value).expect("stdout");
+        writeln!(bf, "\t{}\t{}", name, value).map_err(out)?;
     }
-    write!(bf, "perks\n").expect("stdout");
+    writeln!(bf, "perks").map_err(out)?;
     for (name, value) in attrs.perks {
-        write!(bf, "\t{}\t{}\n", name, value).expect("stdout");
+        writeln!(bf, "\t{}\t{}", name, value).map_err(out)?;
     }
-    write!(bf, "addictions\n").expect("stdout");
+    writeln!(bf, "addictions").map_err(out)?;
     for (name, value) in attrs.addictions {
-        write!(bf, "\t{}\t{}\n", name, value).expect("stdout");
+        writeln!(bf, "\t{}\t{}", name, value).map_err(out)?;
     }
+    Ok(())
+}
+
+fn list_attributes(ent: &Entity) -> Result<(), FE> {
+    log_attributes(ent.get_attributes()?)
 }
 
-fn list_attributes(ent: &Entity) {
-    match ent.get_attributes() {
-        Ok(attrs) => log_attributes(attrs),
-        Err(e) => panic!("Fatal Error {}", e),
+fn list_modifiers(ent: &Entity) -> Result<(), FE> {
+    log_attributes(ent.get_modifiers()?)
+}
+
+fn write_attribute(ent: &mut Entity, group: &str, name: &str, value: &str) -> Result<(), FE> {
+    let mut attrs = ent.get_attributes()?;
+    attrs.set_value(group, name, value)?;
+    ent.set_attributes(attrs)
+}
+
+fn write_modifier(ent: &mut Entity, group: &str, name: &str, value: &str) -> Result<(), FE> {
+    let mut attrs = ent.get_modifiers()?;
+    attrs.set_value(group, name, value)?;
+    ent.set_modifiers(attrs)
+}
+
+fn add_xp(id: usize, ent: &mut Entity, xp: u32) -> Result<(), FE> {
+    let mut attrs = ent.get_attributes()?;
+    let lvl = attrs.add_experience(xp)?;
+    ent.set_attributes(attrs)?;
+
+    // game heals character by the same amount max hit points raised
+    if lvl.hit_points > 0 {
+        let esh = ent.get_esh_mut()?;
+        if let Ok(mut current) = esh.get_nested("Current Attributes") {
+            if let Some(ESHValue::Int(hp)) = current.props.get_mut("hitPoints") {
+                *hp = hp
+                    .checked_add(lvl.hit_points as i32)
+                    .ok_or_else(|| FE::InvalidValue("hitPoints overflow".to_string()))?;
+                esh.set_nested("Current Attributes", current)?;
+            }
+        }
     }
+
+    writeln!(
+        stdout(),
+        "{}\tlevel {} -> {}\tskillPoints +{}\tperksToTake +{}\tmaxHitPoints +{}",
+        id,
+        lvl.old_level,
+        lvl.new_level,
+        lvl.skill_points,
+        lvl.perks,
+        lvl.hit_points
+    )
+    .map_err(out)?;
+    Ok(())
 }
 
-fn list_modifiers(ent: &Entity) {
-    match ent.get_modifiers() {
-        Ok(attrs) => log_attributes(attrs),
-        Err(e) => panic!("Fatal Error {}", e),
+fn character_sheets<'a>(
+    entlist: &EntityList,
+    iter: impl IntoIterator<Item = (usize, &'a Entity)>,
+    format: SheetFormat,
+) -> Result<(), FE> {
+    let mut sheets: Vec<(usize, &Entity)> = iter.into_iter().collect();
+    sheets.sort_by_key(|(id, _)| *id);
+
+    let mut bf = BufWriter::new(stdout().lock());
+    if format == SheetFormat::Json {
+        write!(bf, "[").map_err(out)?;
+    }
+    for (i, (id, ent)) in sheets.into_iter().enumerate() {
+        let sheet = CharacterSheet::from_entity(entlist, id, ent)?;
+        match format {
+            SheetFormat::Text => sheet.write_text(&mut bf),
+            SheetFormat::Markdown => sheet.write_markdown(&mut bf),
+            SheetFormat::Json => {
+                if i > 0 {
+                    write!(bf, ",").map_err(out)?;
+                }
+                sheet.write_json(&mut bf)
+            }
+        }
+        .map_err(write_failed("stdout"))?;
+    }
+    if format == SheetFormat::Json {
+        writeln!(bf, "]").map_err(out)?;
     }
+    Ok(())
 }
 
-fn write_attribute_value(attrs: &mut Attributes, group: &str, name: &str, value: &str) {
-    match group {
-        "stats" => attrs.stats[name] = value.parse().expect("parse"),
-        "traits" => attrs.traits[name] = value.parse().expect("parse"),
-        "derived" => attrs.derived[name] = value.parse().expect("parse"),
-        "skills" => attrs.skills[name] = value.parse().expect("parse"),
-        "skill_tags" => attrs.skill_tags[name] = value.parse().expect("parse"),
-        "opt_traits" => attrs.opt_traits[name] = value.parse().expect("parse"),
-        "perks" => attrs.perks[name] = value.parse().expect("parse"),
-        "addictions" => attrs.addictions[name] = value.parse().expect("parse"),
-        _ => panic!("invalid group specified"),
+// characters shown on character panel have GuiSlot set
+const GUI_SLOT: &str = "GuiSlot";
+
+fn write_tag(w: &mut impl Write, tag: Option<&Tag>) -> std::io::Result<()> {
+    match tag {
+        Some(tag) => writeln!(w, "{} {}", tag.name, tag.version),
+        None => writeln!(w, "-"),
     }
 }
 
-fn write_attribute(ent: &mut Entity, group: &str, name: &str, value: &str) {
-    let mut attrs = ent.get_attributes().expect("get_attributes");
-    write_attribute_value(&mut attrs, group, name, value);
-    ent.set_attributes(attrs).expect("set_attributes");
+fn write_info(doc: &Document, w: &mut impl Write) -> Result<(), FE> {
+    if let Document::Save(save) = doc {
+        if let Some(header) = &save.header {
+            writeln!(w, "{:<18}{}", "name:", header.name)?;
+            writeln!(w, "{:<18}{}", "location:", header.location)?;
+            writeln!(w, "{:<18}{}", "date:", header.date)?;
+        }
+
+        let world = &save.world;
+        writeln!(w, "{:<18}{}", "mission:", world.mission)?;
+        write!(w, "{:<18}", "save tag:")?;
+        write_tag(w, save.header.as_ref().map(|header| &header.tag))?;
+        write!(w, "{:<18}", "world tag:")?;
+        write_tag(w, Some(&world.tag))?;
+        write!(w, "{:<18}", "sgd tag:")?;
+        write_tag(w, Some(&world.sgd.tag))?;
+        write!(w, "{:<18}", "ssg tag:")?;
+        write_tag(w, Some(&world.ssg.tag))?;
+        writeln!(w, "{:<18}{}", "compressed:", world.compressed_len())?;
+        writeln!(w, "{:<18}{}", "uncompressed:", world.uncompressed_size.get())?;
+        writeln!(w, "{:<18}{:?}", "compression:", world.compression)?;
+        writeln!(w, "{:<18}{}", "unparsed tail:", world.unparsed.len())?;
+    }
+
+    let entlist = doc.entlist();
+    let (entity_file_tag, entity_tag) = entlist.get_tags();
+    write!(w, "{:<18}", "entity_file tag:")?;
+    write_tag(w, entity_file_tag)?;
+    write!(w, "{:<18}", "entity tag:")?;
+    write_tag(w, entity_tag)?;
+
+    writeln!(w, "{:<18}{}", "entities:", entlist.len())?;
+    let counts = entlist.count_types();
+    for (idx, type_name) in entlist.get_types().iter().enumerate() {
+        writeln!(w, "\t{}\t{}", counts[idx], type_name)?;
+    }
+
+    writeln!(w, "party:")?;
+    for (id, ent) in entlist {
+        let esh = match ent.esh()? {
+            Some(esh) => esh,
+            None => continue,
+        };
+        let slot = match esh.get(GUI_SLOT) {
+            Some(ESHValue::Int(slot)) if *slot > 0 => *slot,
+            _ => continue,
+        };
+        let name = match esh.get("Display Name") {
+            Some(ESHValue::String(name)) => name.as_str(),
+            _ => "",
+        };
+        writeln!(w, "\t{}\t{}\t{}", slot, id, name)?;
+    }
+    Ok(())
 }
 
-fn write_modifier(ent: &mut Entity, group: &str, name: &str, value: &str) {
-    let mut attrs = ent.get_modifiers().expect("modifiers");
-    write_attribute_value(&mut attrs, group, name, value);
-    ent.set_modifiers(attrs).expect("set_modifiers");
+// decimal or 0x prefixed hex number
+fn parse_number(str: &str) -> Result<u32, FE> {
+    let str = str.trim();
+    match str.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => str.parse(),
+    }
+    .map_err(|_| FE::InvalidValue(str.to_string()))
+}
+
+fn parse_position(position: &str) -> Result<(f32, f32, f32), FE> {
+    let coords: Vec<f32> = position
+        .split(',')
+        .map(|c| c.trim().parse().ok())
+        .collect::<Option<Vec<f32>>>()
+        .ok_or_else(|| FE::InvalidValue(position.to_string()))?;
+    match coords[..] {
+        [a, b, c] => Ok((a, b, c)),
+        _ => Err(FE::InvalidValue(position.to_string())),
+    }
 }
 
-fn do_save(cli: Cli) {
-    let mut save = match Save::load(Path::new(cli.input.as_str())) {
-        Ok(save) => save,
-        Err(fe) => panic!("{}", fe),
+fn write_flag(ent: &mut Entity, flag: &str, link: &Option<String>, value: bool) -> Result<(), FE> {
+    match link {
+        Some(link) => {
+            let flag: LinkFlags =
+                parse_flag(flag).ok_or_else(|| FE::UnknownFlag(flag.to_string()))?;
+            match ent.get_esh_mut()?.props.get_mut(link.as_str()) {
+                Some(ESHValue::EntityFlags(eflags)) => {
+                    let mut flags = eflags.get_flags();
+                    flags.set(flag, value);
+                    eflags.set_flags(flags);
+                    Ok(())
+                }
+                _ => Err(FE::NoESHValue),
+            }
+        }
+        None => {
+            let flag: EntityFlags =
+                parse_flag(flag).ok_or_else(|| FE::UnknownFlag(flag.to_string()))?;
+            let mut flags = ent.get_flags();
+            flags.set(flag, value);
+            ent.set_flags(flags);
+            Ok(())
+        }
+    }
+}
+
+// comma separated attribute groups, all groups when not given
+fn parse_groups(groups: &Option<String>) -> Result<Vec<&str>, FE> {
+    let groups = match groups {
+        Some(groups) => groups,
+        None => return Ok(GROUPS.to_vec()),
     };
-    let entlist = &save.world.entlist;
+
+    groups
+        .split(',')
+        .map(|group| {
+            let group = group.trim();
+            if GROUPS.contains(&group) {
+                Ok(group)
+            } else {
+                Err(FE::NoAttributeGroup(group.to_string()))
+            }
+        })
+        .collect()
+}
+
+fn do_command(cli: Cli) -> Result<(), FE> {
+    let mut doc = Document::load(
+        Path::new(cli.input.as_str()),
+        cli.kind,
+        cli.codepage,
+        cli.lazy,
+    )?;
+    doc.set_compression(cli.compression);
+    let entlist = doc.entlist();
 
     match cli.command {
         Commands::ListEntities => {
-            log_entities(entlist, entlist.into_iter());
+            if cli.type_glob.is_some() {
+                let mut entities: Vec<(usize, &Entity)> =
+                    get_entities(entlist, None, None, cli.type_glob)?
+                        .into_iter()
+                        .collect();
+                entities.sort_by_key(|(id, _)| *id);
+                log_entities(entlist, entities)?;
+            } else {
+                log_entities(entlist, entlist)?;
+            }
         }
         Commands::FindEntities => {
-            log_entities(entlist, find_entities(entlist, cli.find.unwrap()));
+            let find = cli.find.ok_or(FE::NoEntitySelector)?;
+            log_entities(entlist, get_entities(entlist, None, Some(find), cli.type_glob)?)?;
         }
         Commands::ListValues => {
-            for (_, ent) in get_entities(entlist, cli.ids, cli.find) {
-                list_values(ent);
+            for (_, ent) in get_entities(entlist, cli.ids, cli.find, cli.type_glob)? {
+                list_values(ent)?;
             }
         }
         Commands::WriteValue { name, value } => {
-            for (_, ent) in get_entities_mut(&mut save.world.entlist, cli.ids, cli.find) {
-                write_value(ent, &name, &value);
+            for (_, ent) in get_entities_mut(doc.entlist_mut(), cli.ids, cli.find, cli.type_glob)? {
+                write_value(ent, &name, &value)?;
             }
-            save.save(Path::new(&cli.output)).expect("failed to save");
+            save_output(&doc, &cli.output)?;
         }
         Commands::ReadNested { nested } => {
-            for (_, ent) in get_entities(entlist, cli.ids, cli.find) {
-                read_nested(ent, &nested)
+            for (_, ent) in get_entities(entlist, cli.ids, cli.find, cli.type_glob)? {
+                read_nested(ent, &nested)?;
             }
         }
         Commands::WriteNested { nested, name, value } => {
-            for (_, ent) in get_entities_mut(&mut save.world.entlist, cli.ids, cli.find) {
-                write_nested(ent, &nested, &name, &value)
+            for (_, ent) in get_entities_mut(doc.entlist_mut(), cli.ids, cli.find, cli.type_glob)? {
+                write_nested(ent, &nested, &name, &value)?;
             }
-            save.save(Path::new(&cli.output)).expect("failed to save");
+            save_output(&doc, &cli.output)?;
         }
         Commands::ListAttributes => {
-            for (_, ent) in get_entities(entlist, cli.ids, cli.find) {
-                list_attributes(ent);
+            for (_, ent) in get_entities(entlist, cli.ids, cli.find, cli.type_glob)? {
+                list_attributes(ent)?;
             }
         }
         Commands::ListModifiers => {
-            for (_, ent) in get_entities(entlist, cli.ids, cli.find) {
-                list_modifiers(ent);
+            for (_, ent) in get_entities(entlist, cli.ids, cli.find, cli.type_glob)? {
+                list_modifiers(ent)?;
             }
         }
         Commands::WriteAttribute { group, name, value } => {
-            for (_, ent) in get_entities_mut(&mut save.world.entlist, cli.ids, cli.find) {
-                write_attribute(ent, group.as_str(), name.as_str(), value.as_str());
+            for (_, ent) in get_entities_mut(doc.entlist_mut(), cli.ids, cli.find, cli.type_glob)? {
+                write_attribute(ent, group.as_str(), name.as_str(), value.as_str())?;
             }
-            save.save(Path::new(&cli.output)).expect("failed to save");
+            save_output(&doc, &cli.output)?;
         }
         Commands::WriteModifier { group, name, value } => {
-            for (_, ent) in get_entities_mut(&mut save.world.entlist, cli.ids, cli.find) {
-                write_modifier(ent, group.as_str(), name.as_str(), value.as_str());
+            for (_, ent) in get_entities_mut(doc.entlist_mut(), cli.ids, cli.find, cli.type_glob)? {
+                write_modifier(ent, group.as_str(), name.as_str(), value.as_str())?;
+            }
+            save_output(&doc, &cli.output)?;
+        }
+        Commands::CharacterSheet { format } => {
+            character_sheets(
+                entlist,
+                get_entities(entlist, cli.ids, cli.find, cli.type_glob)?,
+                format,
+            )?;
+        }
+        Commands::ExportAttributes {
+            path,
+            groups,
+            modifiers,
+        } => {
+            let entities = get_entities(entlist, cli.ids, cli.find, cli.type_glob)?;
+            if entities.len() != 1 {
+                return Err(FE::NotSingleEntity(entities.len()));
+            }
+            let (_, ent) = entities.into_iter().next().unwrap();
+            AttributesTemplate::from_entity(ent, &parse_groups(&groups)?, modifiers)?
+                .save(Path::new(&path))
+                .map_err(write_failed(&path))?;
+        }
+        Commands::ImportAttributes { path, groups } => {
+            let template = AttributesTemplate::load(Path::new(&path))?;
+            let groups = parse_groups(&groups)?;
+            for (_, ent) in get_entities_mut(doc.entlist_mut(), cli.ids, cli.find, cli.type_glob)? {
+                template.apply(ent, &groups)?;
             }
-            save.save(Path::new(&cli.output)).expect("failed to save");
+            save_output(&doc, &cli.output)?;
         }
+        Commands::ExportDialogs { path, format } => {
+            let dialogs = Dialogs::from_sgd(doc.sgd()?);
+            let text = match format.unwrap_or_else(|| DialogFormat::detect(Path::new(&path))) {
+                DialogFormat::Po => dialogs.to_po(),
+                DialogFormat::Csv => dialogs.to_csv(),
+            };
+            std::fs::write(&path, text).map_err(|e| write_failed(&path)(e.into()))?;
+        }
+        Commands::ImportDialogs { path, format } => {
+            let path = Path::new(&path);
+            let dialogs = match format.unwrap_or_else(|| DialogFormat::detect(path)) {
+                DialogFormat::Po => Dialogs::load_po(path),
+                DialogFormat::Csv => Dialogs::load_csv(path),
+            }?;
+            let count = dialogs.apply(doc.sgd_mut()?)?;
+            writeln!(stdout(), "replaced {} dialog lines", count).map_err(out)?;
+            save_output(&doc, &cli.output)?;
+        }
+        Commands::Info => {
+            let mut bf = BufWriter::new(stdout().lock());
+            write_info(&doc, &mut bf).map_err(write_failed("stdout"))?;
+        }
+        Commands::ShowSsg { set } => {
+            if let Some(set) = set {
+                let invalid = || FE::InvalidValue(set.clone());
+                let (pos, value) = set.split_once('=').ok_or_else(invalid)?;
+                let pos = parse_number(pos)? as usize;
+                let value = parse_number(value)?;
+                let ssg = doc.ssg_mut()?;
+                // position outside SSG data is bad argument, not broken save
+                let old = ssg.get_u32(pos).map_err(|_| invalid())?;
+                ssg.set_u32(pos, value)?;
+                writeln!(stdout(), "{:#x}\t{} -> {}", pos, old, value).map_err(out)?;
+                save_output(&doc, &cli.output)?;
+            }
+
+            let mut bf = BufWriter::new(stdout().lock());
+            doc.ssg()?.write_hex(&mut bf).map_err(write_failed("stdout"))?;
+        }
+        Commands::CloneEntity { count } => {
+            let mut ids: Vec<usize> = get_entities(entlist, cli.ids, cli.find, cli.type_glob)?
+                .into_keys()
+                .collect();
+            ids.sort();
+
+            let entlist = doc.entlist_mut();
+            let mut bf = BufWriter::new(stdout().lock());
+            for id in ids {
+                for _ in 0..count {
+                    let new_id = entlist.clone_entity(id)?;
+                    writeln!(bf, "{}\t{}", id, new_id).map_err(out)?;
+                }
+            }
+            save_output(&doc, &cli.output)?;
+        }
+        Commands::ImportEntity { path, position } => {
+            let entfile = EntityList::load_entfile(Path::new(&path), cli.codepage)?;
+            let position = position.as_deref().map(parse_position).transpose()?;
+
+            let entlist = doc.entlist_mut();
+            let mut bf = BufWriter::new(stdout().lock());
+            for (id, ent) in &entfile {
+                if ent.is_empty() {
+                    continue;
+                }
+
+                let new_id = entlist.import_entity(&entfile, id)?;
+                if let Some((a, b, c)) = position {
+                    entlist.get_entity_mut(new_id)?.set_position(a, b, c)?;
+                }
+                writeln!(bf, "{}", new_id).map_err(out)?;
+            }
+            save_output(&doc, &cli.output)?;
+        }
+        Commands::TransferEntity { destination } => {
+            let mut dest = Document::load(Path::new(&destination), None, cli.codepage, cli.lazy)?;
+            dest.set_compression(cli.compression);
+
+            let mut ids: Vec<usize> = Vec::new();
+            let mut selected: Vec<usize> = get_entities(entlist, cli.ids, cli.find, cli.type_glob)?
+                .into_keys()
+                .collect();
+            selected.sort();
+            for id in selected {
+                for dep in entlist.collect_dependencies(id)? {
+                    if !ids.contains(&dep) {
+                        ids.push(dep);
+                    }
+                }
+            }
+
+            let remap = dest
+                .entlist_mut()
+                .transfer_entities(entlist, &ids)?;
+            for &id in &ids {
+                for (name, target) in entlist.get_entity(id)?.get_references()? {
+                    if target != NO_ENTITY && !remap.contains_key(&target) {
+                        eprintln!(
+                            "warning: {} link \"{}\" to {} is cleared",
+                            id, name, target
+                        );
+                    }
+                }
+            }
+            let mut bf = BufWriter::new(stdout().lock());
+            for id in ids {
+                writeln!(bf, "{}\t{}", id, remap[&id]).map_err(out)?;
+            }
+            save_output(&dest, &cli.output)?;
+        }
+        Commands::ListReferences => {
+            let graph = RefGraph::build(entlist)?;
+            let mut ids: Vec<usize> = get_entities(entlist, cli.ids, cli.find, cli.type_glob)?
+                .into_keys()
+                .collect();
+            ids.sort();
+
+            let mut bf = BufWriter::new(stdout().lock());
+            for id in ids {
+                for r in graph.references_from(id) {
+                    writeln!(bf, "{}\t->\t{}\t{}", id, r.to, r.name).map_err(out)?;
+                }
+                for r in graph.references_to(id) {
+                    writeln!(bf, "{}\t<-\t{}\t{}", id, r.from, r.name).map_err(out)?;
+                }
+            }
+        }
+        Commands::CheckReferences => {
+            let graph = RefGraph::build(entlist)?;
+            let mut bf = BufWriter::new(stdout().lock());
+            for r in graph.dangling(entlist) {
+                writeln!(bf, "{}\t{}\tdangling {}", r.from, r.name, r.to).map_err(out)?;
+            }
+        }
+        Commands::ExportGraph { path } => {
+            let file = File::create(&path).map_err(|e| write_failed(&path)(e.into()))?;
+            let mut bf = BufWriter::new(file);
+            RefGraph::build(entlist)?
+                .write_dot(entlist, &mut bf)
+                .map_err(write_failed(&path))?;
+        }
+        Commands::ListInventory => {
+            let mut ids: Vec<usize> = get_entities(entlist, cli.ids, cli.find, cli.type_glob)?
+                .into_keys()
+                .collect();
+            ids.sort();
+
+            let mut bf = BufWriter::new(stdout().lock());
+            for id in ids {
+                let items = inventory::list_items(entlist, id)?;
+                writeln!(bf, "{}", id).map_err(out)?;
+                for item in items {
+                    writeln!(bf, "\t{}\t{}", item.id, item.type_name).map_err(out)?;
+                }
+            }
+        }
+        Commands::GiveItem { item } => {
+            entlist.get_entity(item)?;
+            let mut ids: Vec<usize> = get_entities(entlist, cli.ids, cli.find, cli.type_glob)?
+                .into_keys()
+                .collect();
+            ids.sort();
+
+            let entlist = doc.entlist_mut();
+            let mut bf = BufWriter::new(stdout().lock());
+            for id in ids {
+                let new_item = inventory::give_item(entlist, id, item)?;
+                writeln!(bf, "{}\t{}", id, new_item).map_err(out)?;
+            }
+            save_output(&doc, &cli.output)?;
+        }
+        Commands::TakeItem { item, to } => {
+            entlist.get_entity(item)?;
+            if let Some(to) = to {
+                entlist.get_entity(to)?;
+            }
+            let ids: Vec<usize> = get_entities(entlist, cli.ids, cli.find, cli.type_glob)?
+                .into_keys()
+                .collect();
+
+            let entlist = doc.entlist_mut();
+            for id in ids {
+                inventory::take_item(entlist, id, item, to)?;
+            }
+            save_output(&doc, &cli.output)?;
+        }
+        Commands::ListTypes { remove_unused } => {
+            {
+                let mut bf = BufWriter::new(stdout().lock());
+                let counts = entlist.count_types();
+                for (idx, type_name) in entlist.get_types().iter().enumerate() {
+                    writeln!(bf, "{}\t{}\t{}", idx, type_name, counts[idx]).map_err(out)?;
+                }
+            }
+
+            if remove_unused {
+                let removed = doc.entlist_mut().remove_unused_types();
+                writeln!(stdout(), "removed {} unused types", removed).map_err(out)?;
+                save_output(&doc, &cli.output)?;
+            }
+        }
+        Commands::SetFlag { flag, link } => {
+            for (_, ent) in get_entities_mut(doc.entlist_mut(), cli.ids, cli.find, cli.type_glob)? {
+                write_flag(ent, &flag, &link, true)?;
+            }
+            save_output(&doc, &cli.output)?;
+        }
+        Commands::ClearFlag { flag, link } => {
+            for (_, ent) in get_entities_mut(doc.entlist_mut(), cli.ids, cli.find, cli.type_glob)? {
+                write_flag(ent, &flag, &link, false)?;
+            }
+            save_output(&doc, &cli.output)?;
+        }
+        Commands::RemoveEntity => {
+            let mut ids: Vec<usize> = get_entities(entlist, cli.ids, cli.find, cli.type_glob)?
+                .into_keys()
+                .collect();
+            ids.sort();
+
+            let mut bf = BufWriter::new(stdout().lock());
+            for &id in ids.iter() {
+                for (ref_id, name) in entlist.find_references(id)? {
+                    if !ids.contains(&ref_id) {
+                        writeln!(bf, "{}\treferenced by {} in \"{}\"", id, ref_id, name)
+                            .map_err(out)?;
+                    }
+                }
+            }
+
+            let entlist = doc.entlist_mut();
+            for id in ids {
+                entlist.remove_entity(id)?;
+            }
+            save_output(&doc, &cli.output)?;
+        }
+        Commands::AddXp { xp } => {
+            for (id, ent) in get_entities_mut(doc.entlist_mut(), cli.ids, cli.find, cli.type_glob)? {
+                add_xp(id, ent, xp)?;
+            }
+            save_output(&doc, &cli.output)?;
+        }
+    }
+    Ok(())
+}
+
+// error with its sources, outermost first
+fn error_chain(fe: &FE) -> String {
+    let mut message = fe.to_string();
+    let mut source = fe.source();
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
     }
+    message
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    do_save(cli);
+    if let Err(fe) = do_command(cli) {
+        eprintln!("{}", error_chain(&fe));
+        std::process::exit(exit_code(&fe));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fot::entitylist::EntityEncoding;
+    use fot::ferror::DecodeOffset;
+    use fot::stream::ReadStream;
+
+    // world entity list of "Item" entities with EntityFlags links,
+    // None is empty slot
+    fn world_bytes(ents: &[Option<&[(&str, usize)]>]) -> Vec<u8> {
+        let mut mem: Vec<u8> = b"<entity_file>\x004\0".to_vec();
+        mem.extend(1u32.to_le_bytes());
+        mem.extend(4u32.to_le_bytes());
+        mem.extend(b"Item");
+        mem.extend((ents.len() as u16 + 1).to_le_bytes());
+        mem.extend(0u32.to_le_bytes());
+        for links in ents {
+            mem.extend(0u32.to_le_bytes());
+            let links = match links {
+                Some(links) => links,
+                None => {
+                    mem.extend(0xFFFFu16.to_le_bytes());
+                    continue;
+                }
+            };
+            mem.extend(0u16.to_le_bytes());
+            mem.extend(b"<esh>\x002\0");
+            mem.extend((links.len() as u32).to_le_bytes());
+            for (name, target) in links.iter() {
+                mem.extend((name.len() as u32).to_le_bytes());
+                mem.extend(name.as_bytes());
+                mem.extend(12u32.to_le_bytes());
+                mem.extend(4u32.to_le_bytes());
+                mem.extend((*target as u16).to_le_bytes());
+                mem.extend(0u16.to_le_bytes());
+            }
+        }
+        mem
+    }
+
+    fn world(ents: &[Option<&[(&str, usize)]>]) -> EntityList {
+        ReadStream::new(&world_bytes(ents), 0)
+            .read_ctx(EntityEncoding::World)
+            .unwrap()
+    }
+
+    // world entity list with entities 1 to 5, where 3 is empty slot
+    fn entlist() -> EntityList {
+        world(&[Some(&[]), Some(&[]), None, Some(&[]), Some(&[])])
+    }
+
+    // character 1 with inventory container 2 holding items 3 and 4,
+    // item 3 links back to its owner, 5 is unrelated entity
+    fn inventory() -> EntityList {
+        world(&[
+            Some(&[("Inventory", 2), ("Hand1", 4)]),
+            Some(&[("Slot0", 3), ("Slot1", 4), ("Slot2", 0)]),
+            Some(&[("Owner", 1)]),
+            Some(&[]),
+            Some(&[("Target", 3)]),
+        ])
+    }
+
+    fn links(entlist: &EntityList, id: usize) -> Vec<(String, usize)> {
+        entlist
+            .get_entity(id)
+            .unwrap()
+            .get_references()
+            .unwrap()
+            .into_iter()
+            .map(|(name, target)| (name.to_string(), target))
+            .collect()
+    }
+
+    fn link(name: &str, target: usize) -> (String, usize) {
+        (name.to_string(), target)
+    }
+
+    #[test]
+    fn transfer_entities_remaps_links() {
+        let from = inventory();
+        let mut entlist = entlist();
+        let ids = from.collect_dependencies(1).unwrap();
+        assert_eq!(ids, vec![1, 2, 4, 3]);
+
+        let remap = entlist.transfer_entities(&from, &ids).unwrap();
+        let mut pairs: Vec<(usize, usize)> = remap.into_iter().collect();
+        pairs.sort();
+        assert_eq!(pairs, vec![(1, 6), (2, 7), (3, 9), (4, 8)]);
+        assert_eq!(entlist.len(), 9);
+
+        assert_eq!(links(&entlist, 6), vec![link("Inventory", 7), link("Hand1", 8)]);
+        assert_eq!(
+            links(&entlist, 7),
+            vec![link("Slot0", 9), link("Slot1", 8), link("Slot2", 0)]
+        );
+        assert_eq!(links(&entlist, 9), vec![link("Owner", 6)]);
+
+        // links to entities that are not transferred are cleared
+        let remap = entlist.transfer_entities(&from, &[5]).unwrap();
+        assert_eq!(remap[&5], 10);
+        assert_eq!(links(&entlist, 10), vec![link("Target", 0)]);
+    }
+
+    fn references(entlist: &EntityList, id: usize) -> Vec<(usize, String)> {
+        entlist
+            .find_references(id)
+            .unwrap()
+            .into_iter()
+            .map(|(from, name)| (from, name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn remove_entity_keeps_ids_and_references() {
+        let mut entlist = inventory();
+        assert_eq!(
+            references(&entlist, 3),
+            vec![(2, "Slot0".to_string()), (5, "Target".to_string())]
+        );
+        assert_eq!(
+            references(&entlist, 4),
+            vec![(1, "Hand1".to_string()), (2, "Slot1".to_string())]
+        );
+        assert!(references(&entlist, 5).is_empty());
+
+        entlist.remove_entity(3).unwrap();
+        assert_eq!(entlist.len(), 5);
+        assert!(entlist.get_entity(3).unwrap().is_empty());
+        assert!(!entlist.is_valid_id(3));
+        // other entities keep their ids and links, so removed one is still referenced
+        assert_eq!(links(&entlist, 5), vec![link("Target", 3)]);
+        assert_eq!(
+            references(&entlist, 3),
+            vec![(2, "Slot0".to_string()), (5, "Target".to_string())]
+        );
+        assert!(matches!(entlist.remove_entity(6), Err(FE::NoEntity(6))));
+    }
+
+    #[test]
+    fn ref_graph_dangling() {
+        let mut entlist = world(&[
+            Some(&[("Hand1", 2), ("Hand2", 0), ("Target", 9)]),
+            Some(&[("Owner", 1)]),
+            Some(&[("Link", 2)]),
+        ]);
+        entlist.remove_entity(3).unwrap();
+        let graph = RefGraph::build(&entlist).unwrap();
+        // empty links are not references, removed entity keeps no links
+        assert_eq!(graph.refs.len(), 2 + 1);
+        assert_eq!(graph.references_from(1).count(), 2);
+        assert_eq!(graph.references_to(1).count(), 1);
+
+        let dangling: Vec<(usize, &str, usize)> = graph
+            .dangling(&entlist)
+            .map(|r| (r.from, r.name.as_str(), r.to))
+            .collect();
+        assert_eq!(dangling, vec![(1, "Target", 9)]);
+
+        entlist.remove_entity(2).unwrap();
+        let graph = RefGraph::build(&entlist).unwrap();
+        let dangling: Vec<usize> = graph.dangling(&entlist).map(|r| r.to).collect();
+        assert_eq!(dangling, vec![2, 9]);
+    }
+
+    #[test]
+    fn ref_graph_write_dot() {
+        let entlist = world(&[Some(&[("Hand1", 2), ("Say \"hi\"", 3)]), Some(&[]), None]);
+        let mut dot: Vec<u8> = Vec::new();
+        RefGraph::build(&entlist)
+            .unwrap()
+            .write_dot(&entlist, &mut dot)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            "digraph entities {\n\
+             \tn1 [label=\"1 Item\"];\n\
+             \tn2 [label=\"2 Item\"];\n\
+             \tn3 [label=\"3 <missing>\", style=dashed, color=red];\n\
+             \tn1 -> n2 [label=\"Hand1\"];\n\
+             \tn1 -> n3 [label=\"Say \\\"hi\\\"\"];\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn decode_rejects_unknown_type() {
+        let mut mem = world_bytes(&[None, Some(&[])]);
+        // type index of entity 2, after header, types and entity 1
+        mem[44..46].copy_from_slice(&1u16.to_le_bytes());
+        let fe = ReadStream::new(&mem, 0)
+            .read_ctx::<EntityList, _, _>(EntityEncoding::World)
+            .err()
+            .unwrap();
+        assert_eq!(exit_code(&fe), EXIT_DECODE_FAILURE);
+        assert!(matches!(fe, FE::Decode(path, DecodeOffset::File(40), source)
+            if path == ["entity 2"] && matches!(*source, FE::NoType(1))));
+    }
+
+    #[test]
+    fn error_chain_lists_sources() {
+        let fe = FE::NoType(7)
+            .context("entity 2", 0x28)
+            .world_context("EntityList", 0x10)
+            .context("World", 0x100);
+        assert!(matches!(fe, FE::Decode(_, DecodeOffset::World(0x28), _)));
+        assert_eq!(
+            error_chain(&fe),
+            "World > EntityList > entity 2 at world+0x28: No entity type with index 7"
+        );
+
+        let fe = FE::WriteFailed("out.sav".to_string(), Box::new(FE::NoType(1).context("x", 4)));
+        assert_eq!(
+            error_chain(&fe),
+            "Failed to write out.sav: x at offset 0x4: No entity type with index 1"
+        );
+        assert!(fe.source().unwrap().source().unwrap().source().is_none());
+    }
+
+    #[test]
+    fn clone_entity_clones_owned() {
+        let mut entlist = inventory();
+        assert_eq!(entlist.clone_entity(1).unwrap(), 6);
+        assert_eq!(entlist.len(), 9);
+        // clone owns cloned container and items, not the original ones,
+        // owned entities are cloned in order 2, 4, 3
+        assert_eq!(links(&entlist, 6), vec![link("Inventory", 7), link("Hand1", 8)]);
+        assert_eq!(
+            links(&entlist, 7),
+            vec![link("Slot0", 9), link("Slot1", 8), link("Slot2", 0)]
+        );
+        assert_eq!(links(&entlist, 9), vec![link("Owner", 6)]);
+        // originals are left as they are
+        assert_eq!(links(&entlist, 1), vec![link("Inventory", 2), link("Hand1", 4)]);
+        assert_eq!(links(&entlist, 3), vec![link("Owner", 1)]);
+
+        // links to entities that are not owned are kept
+        assert_eq!(entlist.clone_entity(5).unwrap(), 10);
+        assert_eq!(links(&entlist, 10), vec![link("Target", 3)]);
+    }
+
+    #[test]
+    fn parse_number_decimal_and_hex() {
+        assert_eq!(parse_number("42").unwrap(), 42);
+        assert_eq!(parse_number(" 0x10 ").unwrap(), 16);
+        assert_eq!(parse_number("0xDEADbeef").unwrap(), 0xdeadbeef);
+        assert_eq!(parse_number("4294967295").unwrap(), u32::MAX);
+        assert!(matches!(parse_number("4294967296"), Err(FE::InvalidValue(_))));
+        assert!(parse_number("10h").is_err());
+        assert!(parse_number("ff").is_err());
+        assert!(parse_number("0x").is_err());
+        assert!(parse_number("-1").is_err());
+        assert!(parse_number("").is_err());
+    }
+
+    #[test]
+    fn parse_position_three_coords() {
+        assert_eq!(parse_position("1,2,3").unwrap(), (1., 2., 3.));
+        assert_eq!(parse_position(" 1.5, -2 ,0.25").unwrap(), (1.5, -2., 0.25));
+        assert!(matches!(parse_position("1,2"), Err(FE::InvalidValue(_))));
+        assert!(parse_position("1,2,3,4").is_err());
+        assert!(parse_position("1,x,3").is_err());
+        assert!(parse_position("1,,3").is_err());
+        assert!(parse_position("").is_err());
+    }
+
+    #[test]
+    fn parse_groups_known_only() {
+        assert_eq!(parse_groups(&None).unwrap(), GROUPS.to_vec());
+        let groups = Some("stats, skills".to_string());
+        assert_eq!(parse_groups(&groups).unwrap(), vec!["stats", "skills"]);
+        let typo = Some("stats,skill".to_string());
+        assert!(matches!(parse_groups(&typo), Err(FE::NoAttributeGroup(g)) if g == "skill"));
+        assert!(parse_groups(&Some(String::new())).is_err());
+    }
+
+    #[test]
+    fn parse_ids_lists_and_ranges() {
+        let entlist = entlist();
+        assert_eq!(parse_ids(&entlist, "1,4").unwrap(), vec![1, 4]);
+        assert_eq!(parse_ids(&entlist, " 5, 1,1 ").unwrap(), vec![1, 5]);
+        assert_eq!(parse_ids(&entlist, "2-4").unwrap(), vec![2, 4]);
+        assert_eq!(parse_ids(&entlist, "3").unwrap(), vec![3]);
+        assert_eq!(parse_ids(&entlist, "3,2-3").unwrap(), vec![2, 3]);
+        assert_eq!(parse_ids(&entlist, "4-4").unwrap(), vec![4]);
+        assert!(matches!(parse_ids(&entlist, "4-2"), Err(FE::InvalidValue(_))));
+        assert!(matches!(parse_ids(&entlist, "6"), Err(FE::NoEntity(6))));
+        assert!(parse_ids(&entlist, "0").is_err());
+        assert!(parse_ids(&entlist, "x").is_err());
+        assert!(parse_ids(&entlist, "1-").is_err());
+    }
+
+    #[test]
+    fn parse_ids_exclusions() {
+        let entlist = entlist();
+        // only exclusions select every non-empty entity except them
+        assert_eq!(parse_ids(&entlist, "!2").unwrap(), vec![1, 4, 5]);
+        assert_eq!(parse_ids(&entlist, "!1-2,!5").unwrap(), vec![4]);
+        assert_eq!(parse_ids(&entlist, "1-5,!2-3").unwrap(), vec![1, 4, 5]);
+        assert_eq!(parse_ids(&entlist, "!4,1-5").unwrap(), vec![1, 2, 5]);
+        assert_eq!(parse_ids(&entlist, "1-5,!2-4,!5").unwrap(), vec![1]);
+        assert!(matches!(parse_ids(&entlist, "!3-2"), Err(FE::InvalidValue(_))));
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("Weapon_Pistol", "Weapon_Pistol"));
+        assert!(!glob_match("Weapon_Pistol", "Weapon_Pistol2"));
+        assert!(glob_match("*Weapon*", "Weapon_Pistol"));
+        assert!(glob_match("*Pistol", "Weapon_Pistol"));
+        assert!(!glob_match("*Pistol", "Weapon_Pistol_Ammo"));
+        assert!(glob_match("Weapon_?istol", "Weapon_Pistol"));
+        assert!(!glob_match("Weapon_?", "Weapon_"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("**", "anything"));
+        assert!(!glob_match("", "x"));
+        assert!(glob_match("Атака*", "Атака_1"));
+    }
 }